@@ -1,12 +1,15 @@
 use camino::{Utf8Path, Utf8PathBuf};
+use chrono::Duration;
 use clap::Args;
 use color_eyre::{
     eyre::{eyre, Context},
     Result,
 };
-use samepic::{ImageData, DATETIME_FORMATTER};
+use samepic::{Image, ImageData, ImageLoadError, TimestampSource, UNREADABLE_PILE};
 
 use crate::common::{create_dir_from_ref_name, dir};
+use crate::layout::Layout;
+use crate::template::{Template, TemplateContext};
 
 /// Collects all remaining images back into one folder after manual sorting is finished
 #[derive(Debug, Args)]
@@ -21,14 +24,41 @@ pub struct Collect {
     #[clap(short, long, value_parser)]
     no_delete: bool,
     /// Do not rename the source images during collection
-    #[clap(short, long, value_parser)]
+    #[clap(short, long, value_parser, conflicts_with = "template")]
     keep_names: bool,
+    /// Template for the names of collected images. Available placeholders are
+    /// {date}, {date:<strftime format>}, {camera}, {orig_stem}, {pile}, {seq}, {hash8}, {ext} and {ext_lower}.
+    /// Defaults to `{date}.{ext}`
+    #[clap(short, long, value_parser = Template::parse)]
+    template: Option<Template>,
+    /// Write the timestamp into the EXIF data of collected images that do not have an EXIF date yet.
+    /// Affected images are copied instead of linked so the originals stay untouched
+    #[clap(short, long, value_parser)]
+    write_exif: bool,
+    /// Folder structure to collect the images into
+    #[clap(short, long, value_enum, default_value_t = Layout::Flat)]
+    layout: Layout,
+    /// Minimum time gap in hours between two images to start a new event with `--layout event`
+    #[clap(short, long, value_parser, default_value_t = 12)]
+    event_gap: u32,
 }
 
 impl Collect {
     pub fn run(self) -> Result<()> {
         let destination = create_dir_from_ref_name(self.destination, &self.source, "final")?;
-        collect(&self.source, &destination, self.keep_names)?;
+        let template = match self.template {
+            Some(template) => template,
+            None if self.keep_names => Template::original_name(),
+            None => Template::timestamp(),
+        };
+        collect(
+            &self.source,
+            &destination,
+            &template,
+            self.write_exif,
+            self.layout,
+            Duration::hours(self.event_gap.into()),
+        )?;
         if !self.no_delete {
             std::fs::remove_dir_all(&self.source)?;
         };
@@ -36,54 +66,153 @@ impl Collect {
     }
 }
 
-fn collect(source: &Utf8Path, destination: &Utf8Path, keep_names: bool) -> Result<()> {
-    for dir in source.read_dir_utf8()? {
-        let dir = dir?;
+struct Entry {
+    path: Utf8PathBuf,
+    pile: String,
+}
+
+fn collect(
+    source: &Utf8Path,
+    destination: &Utf8Path,
+    template: &Template,
+    write_exif: bool,
+    layout: Layout,
+    event_gap: Duration,
+) -> Result<()> {
+    let mut entries = Vec::new();
+    for dir in sorted_entries(source)? {
         if !dir.metadata()?.is_dir() {
             tracing::info!("Skipping {} because it is not a directory.", dir.path());
             continue;
         }
+        if dir.file_name() == UNREADABLE_PILE {
+            tracing::info!(
+                "Skipping {} because its files could not be loaded.",
+                dir.path()
+            );
+            continue;
+        }
+        gather_pile(dir.path(), &mut entries)?;
+    }
 
-        tracing::info!("Disassembling pile {}", dir.path());
-        for image in dir.path().read_dir_utf8()? {
-            let image = image?;
+    // every file is read once to tell images from other files,
+    // only images that get an EXIF timestamp are read again when they are copied
+    let mut images = Vec::with_capacity(entries.len());
+    let mut current_pile = None;
+    for entry in &entries {
+        if current_pile != Some(&entry.pile) {
+            tracing::info!("Disassembling pile {}", entry.pile);
+            current_pile = Some(&entry.pile);
+        }
 
-            let link = generate_file_name(image.path(), destination, keep_names)?;
-            std::fs::hard_link(image.path(), &link)
+        let Some(data) = load_data(&entry.path)? else {
+            // e.g. notes or `.DS_Store`, which are kept under their original name
+            tracing::info!(
+                "Keeping {} unchanged because it is not an image.",
+                entry.path
+            );
+            let name = entry.path.file_name().expect("gathered entries are files");
+            let link = generate_file_name(name, destination)?;
+            std::fs::hard_link(&entry.path, &link)
                 .wrap_err_with(|| format!("Failed to create file {link}"))?;
+            continue;
+        };
+        let hashed = if template.needs_image() {
+            Some(Image::load(&entry.path)?)
+        } else {
+            None
+        };
+
+        let name = template.render(&TemplateContext {
+            original: &entry.path,
+            pile: &entry.pile,
+            seq: images.len() + 1,
+            data: &data,
+            image: hashed.as_ref(),
+        })?;
+        let add_exif = write_exif && data.timestamp_source == TimestampSource::FileSystem;
+        images.push((entry, name, data.timestamp, add_exif));
+    }
+
+    let timestamps: Vec<_> = images
+        .iter()
+        .map(|&(_, _, timestamp, _)| timestamp)
+        .collect();
+    let folders = layout.folders(&timestamps, event_gap);
+    for ((entry, name, timestamp, add_exif), folder) in images.into_iter().zip(folders) {
+        let target_dir = destination.join(folder);
+        std::fs::create_dir_all(&target_dir)
+            .wrap_err_with(|| format!("Cannot create directory {target_dir}"))?;
+        let link = generate_file_name(&name, &target_dir)?;
+
+        if add_exif {
+            let mut data = ImageData::load(&entry.path)?;
+            // the access time may have changed by reading the file before
+            data.timestamp = timestamp;
+            match data.save_with_exif_timestamp(&link) {
+                Ok(()) => continue,
+                Err(err) => tracing::warn!("Failed to write EXIF data for {}: {err}", entry.path),
+            }
         }
+        std::fs::hard_link(&entry.path, &link)
+            .wrap_err_with(|| format!("Failed to create file {link}"))?;
     }
     Ok(())
 }
 
-fn generate_file_name(
-    original: &Utf8Path,
-    target_dir: &Utf8Path,
-    keep_names: bool,
-) -> Result<Utf8PathBuf> {
-    let new_stem: std::borrow::Cow<_> = if keep_names {
-        original
-            .file_stem()
-            .ok_or_else(|| eyre!("Invalid file stem for path {}", original))?
-            .into()
-    } else {
-        let img = ImageData::load(original)?;
-        img.timestamp.format(DATETIME_FORMATTER).to_string().into()
-    };
-
-    let extension = original
-        .extension()
-        .ok_or_else(|| eyre!("Missing file extension for path {}", original))?;
-
-    let mut new_name = format!("{new_stem}.{extension}");
-    let mut same_name_count = 0;
+/// Loads the image data of `path`, `None` if the file is no image
+fn load_data(path: &Utf8Path) -> Result<Option<ImageData>> {
+    match ImageData::load(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(ImageLoadError::NotAnImage(kind)) => {
+            tracing::debug!("File type {kind} of {path} is not an image");
+            Ok(None)
+        }
+        Err(err) => Err(err).wrap_err_with(|| format!("Failed to load {path}")),
+    }
+}
 
-    let mut link = target_dir.to_owned();
-    link.push(&new_name);
+/// Adds all images of the pile in `dir` to `entries`. Nested folders such as the piles of an event folder are gathered recursively
+fn gather_pile(dir: &Utf8Path, entries: &mut Vec<Entry>) -> Result<()> {
+    let pile = dir
+        .file_name()
+        .ok_or_else(|| eyre!("Invalid pile directory {dir}"))?;
+    for entry in sorted_entries(dir)? {
+        if entry.metadata()?.is_dir() {
+            gather_pile(entry.path(), entries)?;
+        } else {
+            entries.push(Entry {
+                path: entry.path().to_owned(),
+                pile: pile.to_owned(),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn sorted_entries(dir: &Utf8Path) -> Result<Vec<camino::Utf8DirEntry>> {
+    let mut entries = dir
+        .read_dir_utf8()?
+        .collect::<Result<Vec<_>, std::io::Error>>()?;
+    entries.sort_unstable_by(|l, r| l.path().cmp(r.path()));
+    Ok(entries)
+}
 
+fn generate_file_name(name: &str, target_dir: &Utf8Path) -> Result<Utf8PathBuf> {
+    let mut link = target_dir.join(name);
+    let new_stem = link
+        .file_stem()
+        .ok_or_else(|| eyre!("Invalid file name {name}"))?
+        .to_owned();
+    let extension = link.extension().map(str::to_owned);
+
+    let mut same_name_count = 0;
     while link.exists() {
         same_name_count += 1;
-        new_name = format!("{new_stem}-{same_name_count}.{extension}");
+        let new_name = match extension {
+            Some(ref extension) => format!("{new_stem}-{same_name_count}.{extension}"),
+            None => format!("{new_stem}-{same_name_count}"),
+        };
         link.set_file_name(new_name);
     }
 
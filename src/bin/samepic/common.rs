@@ -3,11 +3,26 @@ use color_eyre::{
     eyre::{eyre, Context},
     Help, Result,
 };
+use samepic::LoadFailure;
 
 pub fn create_dir_from_ref_name(
     dir: Option<Utf8PathBuf>,
     base: &Utf8Path,
     name_suffix: &str,
+) -> Result<Utf8PathBuf> {
+    let dir = existing_dir_from_ref_name(dir, base, name_suffix)?;
+    match std::fs::read_dir(&dir)?.next() {
+        Some(_) => Err(eyre!("Target directory not empty."))
+            .suggestion("Pass an empty or non-existent target directory."),
+        None => Ok(dir),
+    }
+}
+
+/// Like [`create_dir_from_ref_name`], but the directory may already contain files
+pub fn existing_dir_from_ref_name(
+    dir: Option<Utf8PathBuf>,
+    base: &Utf8Path,
+    name_suffix: &str,
 ) -> Result<Utf8PathBuf> {
     let dir = dir.unwrap_or_else(|| {
         let mut dest = base.to_owned();
@@ -18,11 +33,7 @@ pub fn create_dir_from_ref_name(
         dest
     });
     std::fs::create_dir_all(&dir).wrap_err_with(|| format!("Cannot create directory {}.", dir))?;
-    match std::fs::read_dir(&dir)?.next() {
-        Some(_) => Err(eyre!("Target directory not empty."))
-            .suggestion("Pass an empty or non-existent target directory."),
-        None => Ok(dir),
-    }
+    Ok(dir)
 }
 
 pub fn dir(s: &str) -> Result<Utf8PathBuf> {
@@ -31,3 +42,52 @@ pub fn dir(s: &str) -> Result<Utf8PathBuf> {
         .then(|| s.into())
         .ok_or_else(|| eyre!("Source is not a directory."))
 }
+
+/// Parses a file size in bytes with an optional `K`, `M` or `G` suffix (powers of 1024)
+pub fn size(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let (number, factor) = match s.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&s[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&s[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+    let number: u64 = number
+        .trim()
+        .parse()
+        .wrap_err_with(|| format!("Invalid file size {s}"))?;
+    number
+        .checked_mul(factor)
+        .ok_or_else(|| eyre!("File size {s} is too large"))
+}
+
+pub fn log_failures(failures: &[LoadFailure]) {
+    for failure in failures {
+        tracing::error!(
+            "Failed to load image {}: {}",
+            failure.path,
+            failure.message()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_sizes() {
+        assert_eq!(size("0").unwrap(), 0);
+        assert_eq!(size("1500").unwrap(), 1500);
+        assert_eq!(size("4k").unwrap(), 4 << 10);
+        assert_eq!(size(" 10 M ").unwrap(), 10 << 20);
+        assert_eq!(size("2G").unwrap(), 2 << 30);
+    }
+
+    #[test]
+    fn reject_invalid_sizes() {
+        for s in ["", "K", "-1", "1.5M", "10T", "20000000000G"] {
+            assert!(size(s).is_err(), "accepted {s:?}");
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use camino::Utf8PathBuf;
+use clap::Args;
+use color_eyre::{eyre::Context, Result};
+use samepic::{LoadOptions, Repository, ScanOptions};
+
+use crate::common::{create_dir_from_ref_name, dir, log_failures};
+use crate::progress::TerminalProgress;
+
+/// Compares two image folders and lists the images that are only in one of them.
+///
+/// Images only in `first` are prefixed with `<`, images only in `second` with `>`.
+/// Near-duplicates are prefixed with `=` followed by their hash distance and both paths.
+/// Hidden files and folders are skipped like in `sort`.
+#[derive(Debug, Args)]
+pub struct Diff {
+    /// Folder with the existing images
+    #[clap(value_parser = dir)]
+    first: Utf8PathBuf,
+    /// Folder with the images to be compared against `first`
+    #[clap(value_parser = dir)]
+    second: Utf8PathBuf,
+    /// Hash distance below which two images are considered near-duplicates. Smaller values are stricter
+    #[clap(short, long, value_parser, default_value_t = 10)]
+    threshold: u32,
+    /// Also recognize rotated and mirrored copies of an image. Slows down loading the images
+    #[clap(long, value_parser)]
+    rotation_invariant: bool,
+    /// Link the images only in `second` into this folder, keeping their relative paths.
+    /// If it does not exist, it will be created
+    #[clap(short, long, value_parser)]
+    stage: Option<Utf8PathBuf>,
+}
+
+impl Diff {
+    pub fn run(self) -> Result<()> {
+        let loading = LoadOptions {
+            rotation_invariant: self.rotation_invariant,
+            ..Default::default()
+        };
+        let scanning = ScanOptions::default();
+        let progress = TerminalProgress::new();
+        let first = Repository::scan(
+            std::slice::from_ref(&self.first),
+            &scanning,
+            &loading,
+            &progress,
+        );
+        let second = Repository::scan(
+            std::slice::from_ref(&self.second),
+            &scanning,
+            &loading,
+            &progress,
+        );
+        progress.finish();
+        log_failures(first.failures());
+        log_failures(second.failures());
+        let diff = samepic::diff(first.images(), second.images(), self.threshold);
+
+        for image in &diff.only_left {
+            println!("<\t{}", image.path());
+        }
+        for image in &diff.only_right {
+            println!(">\t{}", image.path());
+        }
+        for (existing, new, distance) in &diff.duplicates {
+            println!("=\t{distance}\t{}\t{}", existing.path(), new.path());
+        }
+        tracing::info!(
+            "{} images only in {}, {} images only in {}, {} near-duplicates",
+            diff.only_left.len(),
+            self.first,
+            diff.only_right.len(),
+            self.second,
+            diff.duplicates.len()
+        );
+
+        if let Some(stage) = self.stage {
+            let stage = create_dir_from_ref_name(Some(stage), &self.second, "new")?;
+            for image in &diff.only_right {
+                let relative = image.path().strip_prefix(&self.second).wrap_err_with(|| {
+                    format!("Image {} is outside of {}", image.path(), self.second)
+                })?;
+                let link = stage.join(relative);
+                if let Some(parent) = link.parent() {
+                    std::fs::create_dir_all(parent)
+                        .wrap_err_with(|| format!("Cannot create directory {parent}"))?;
+                }
+                std::fs::hard_link(image.path(), &link)
+                    .wrap_err_with(|| format!("Failed to link {} to {link}", image.path()))?;
+            }
+        }
+        Ok(())
+    }
+}
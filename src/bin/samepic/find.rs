@@ -0,0 +1,57 @@
+use camino::Utf8PathBuf;
+use clap::Args;
+use color_eyre::{eyre::Context, Result};
+use samepic::{Image, LoadOptions, Repository, ScanOptions};
+
+use crate::common::{dir, log_failures};
+use crate::progress::TerminalProgress;
+
+/// Lists all images in source that are similar to a reference image without sorting them
+#[derive(Debug, Args)]
+pub struct Find {
+    /// Image to search for
+    #[clap(value_parser)]
+    image: Utf8PathBuf,
+    /// Folder to search in. Hidden files and folders are skipped like in `sort`
+    #[clap(value_parser = dir)]
+    source: Utf8PathBuf,
+    /// Hash distance below which an image matches. Smaller values are stricter
+    #[clap(short, long, value_parser, default_value_t = 10)]
+    threshold: u32,
+    /// Also find rotated and mirrored copies of the image. Slows down loading the images
+    #[clap(long, value_parser)]
+    rotation_invariant: bool,
+}
+
+impl Find {
+    pub fn run(self) -> Result<()> {
+        let loading = LoadOptions {
+            rotation_invariant: self.rotation_invariant,
+            ..Default::default()
+        };
+        let reference = Image::load_with_options(&self.image, &loading)
+            .wrap_err_with(|| format!("Failed to load image {}", self.image))?;
+        let reference_path = self.image.canonicalize().ok();
+
+        let progress = TerminalProgress::new();
+        let repo = Repository::scan(
+            std::slice::from_ref(&self.source),
+            &ScanOptions::default(),
+            &loading,
+            &progress,
+        );
+        progress.finish();
+        log_failures(repo.failures());
+        for (image, distance) in repo.find_similar(&reference, self.threshold) {
+            if reference_path.is_some() && image.path().canonicalize().ok() == reference_path {
+                continue;
+            }
+            println!(
+                "{distance}\t{}\t{}",
+                image.timestamp.format("%F %T"),
+                image.path()
+            );
+        }
+        Ok(())
+    }
+}
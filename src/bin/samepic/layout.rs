@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+
+use camino::Utf8PathBuf;
+use chrono::{Duration, NaiveDateTime};
+use clap::ValueEnum;
+
+/// Folder structure of the collected images
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Layout {
+    /// All images directly in the destination folder
+    Flat,
+    /// One folder per day, e.g. `2022/07/14`
+    Day,
+    /// One folder per event, e.g. `2022/2022-07-14`. A new event starts whenever
+    /// there is a large enough time gap between two consecutive images
+    Event,
+}
+
+impl Layout {
+    /// Computes the folder relative to the destination for each of the given image timestamps.
+    ///
+    /// The returned folders are in the same order as `timestamps`.
+    pub fn folders(self, timestamps: &[NaiveDateTime], event_gap: Duration) -> Vec<Utf8PathBuf> {
+        match self {
+            Layout::Flat => vec![Utf8PathBuf::new(); timestamps.len()],
+            Layout::Day => timestamps
+                .iter()
+                .map(|ts| ts.format("%Y/%m/%d").to_string().into())
+                .collect(),
+            Layout::Event => event_folders(timestamps, event_gap),
+        }
+    }
+}
+
+fn event_folders(timestamps: &[NaiveDateTime], event_gap: Duration) -> Vec<Utf8PathBuf> {
+    let mut order: Vec<_> = (0..timestamps.len()).collect();
+    order.sort_by_key(|&i| timestamps[i]);
+
+    let mut folders = vec![Utf8PathBuf::new(); timestamps.len()];
+    let mut events_per_day = HashMap::new();
+    let mut current = Utf8PathBuf::new();
+    let mut previous: Option<NaiveDateTime> = None;
+
+    for i in order {
+        let timestamp = timestamps[i];
+        if previous.is_none_or(|previous| timestamp - previous > event_gap) {
+            let date = timestamp.date();
+            let n: usize = *events_per_day
+                .entry(date)
+                .and_modify(|e| *e += 1)
+                .or_insert(1);
+            current = match n {
+                1 => format!("{}/{date}", date.format("%Y")),
+                n => format!("{}/{date}_{n}", date.format("%Y")),
+            }
+            .into();
+            tracing::debug!("Starting event {current} with image at {timestamp}");
+        }
+        previous = Some(timestamp);
+        folders[i] = current.clone();
+    }
+
+    folders
+}
@@ -5,8 +5,15 @@ use color_eyre::Result;
 mod collect;
 mod common;
 mod completions;
+mod diff;
+mod find;
+mod layout;
 mod open;
+mod progress;
 mod sort;
+mod stats;
+mod template;
+mod watch;
 
 fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
@@ -17,6 +24,10 @@ fn main() -> Result<()> {
         Commands::Sort(sort) => sort.run(),
         Commands::Open(open) => open.run(),
         Commands::Collect(collect) => collect.run(),
+        Commands::Find(find) => find.run(),
+        Commands::Diff(diff) => diff.run(),
+        Commands::Stats(stats) => stats.run(),
+        Commands::Watch(watch) => watch.run(),
         Commands::Completions(completions) => {
             completions.run();
             Ok(())
@@ -34,8 +45,12 @@ struct Args {
 
 #[derive(Debug, Subcommand)]
 enum Commands {
-    Sort(sort::Sort),
+    Sort(Box<sort::Sort>),
     Open(open::Open),
     Collect(collect::Collect),
+    Find(find::Find),
+    Diff(diff::Diff),
+    Stats(stats::Stats),
+    Watch(watch::Watch),
     Completions(completions::Completions),
 }
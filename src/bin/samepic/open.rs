@@ -17,7 +17,7 @@ pub struct OpenOptions {
     /// Program to open the picture folders with. Defaults to the default folder explorer
     #[clap(short, long, value_parser = program)]
     opener: Option<PathBuf>,
-    /// Skip folders with only a single image
+    /// Skip folders (piles or events) with only a single image
     #[clap(short, long)]
     skip_singles: bool,
 }
@@ -126,12 +126,17 @@ fn program(s: &str) -> Result<PathBuf> {
     which(s).wrap_err_with(|| format!("Opener {s} is not a valid executable."))
 }
 
+/// Checks whether the folder contains at most one image, including images in nested pile folders of an event
 fn only_single_file(dir: &Path) -> bool {
-    match dir.read_dir() {
-        Ok(dir) => !dir.enumerate().any(|(i, _)| i >= 1),
-        Err(e) => {
-            tracing::warn!("Failed read directory {}: {e}", dir.display());
-            false
-        }
-    }
+    let mut files = walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|entry| match entry {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                tracing::warn!("Failed read directory {}: {e}", dir.display());
+                None
+            }
+        })
+        .filter(|entry| entry.file_type().is_file());
+    files.nth(1).is_none()
 }
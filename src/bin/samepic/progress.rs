@@ -0,0 +1,64 @@
+use std::io::IsTerminal;
+
+use camino::Utf8Path;
+use indicatif::{ProgressBar, ProgressStyle};
+use samepic::{ImageLoadError, Progress};
+
+const TEMPLATE: &str = "{msg:>9} [{elapsed_precise}] {wide_bar} {human_pos}/{human_len} ({eta})";
+
+/// Progress bar on stderr that shows the current phase. Hidden if stderr is not a terminal
+pub struct TerminalProgress {
+    bar: ProgressBar,
+}
+
+impl TerminalProgress {
+    pub fn new() -> Self {
+        let bar = if std::io::stderr().is_terminal() {
+            ProgressBar::new(0)
+        } else {
+            ProgressBar::hidden()
+        };
+        bar.set_style(ProgressStyle::with_template(TEMPLATE).expect("valid progress template"));
+        Self { bar }
+    }
+
+    fn start(&self, phase: &'static str, total: u64) {
+        self.bar.reset();
+        self.bar.set_length(total);
+        self.bar.set_message(phase);
+    }
+
+    pub fn finish(&self) {
+        self.bar.finish_and_clear();
+    }
+}
+
+impl Progress for TerminalProgress {
+    fn files_discovered(&self, count: usize) {
+        self.start("Loading", count as u64);
+    }
+
+    fn image_loaded(&self, _path: &Utf8Path) {
+        self.bar.inc(1);
+    }
+
+    fn image_failed(&self, _path: &Utf8Path, _error: &ImageLoadError) {
+        self.bar.inc(1);
+    }
+
+    fn comparing_pairs(&self, total: u64) {
+        self.start("Comparing", total);
+    }
+
+    fn pairs_compared(&self, count: u64) {
+        self.bar.inc(count);
+    }
+
+    fn writing_piles(&self, total: usize) {
+        self.start("Writing", total as u64);
+    }
+
+    fn pile_written(&self, _path: &Utf8Path) {
+        self.bar.inc(1);
+    }
+}
@@ -1,35 +1,315 @@
 use camino::Utf8PathBuf;
-use clap::Args;
-use color_eyre::Result;
-use samepic::Repository;
+use chrono::Duration;
+use clap::{Args, ValueEnum};
+use color_eyre::{eyre::Context, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use samepic::{
+    ExistingPiles, FeatureMatching, Gazetteer, Grouper, GroupingOptions, HashVoting, Linkage,
+    LoadOptions, OutputOptions, PileWriter, Refinement, ScanOptions, Scanner, Stats,
+};
 
-use crate::common::{create_dir_from_ref_name, dir};
+use crate::common::{
+    create_dir_from_ref_name, dir, existing_dir_from_ref_name, log_failures, size,
+};
 use crate::open::{Open, OpenOptions};
+use crate::progress::TerminalProgress;
 
-/// Starts grouping all the images in source into a destination folder
+/// Starts grouping all the images in the sources into a destination folder
 #[derive(Debug, Args)]
 pub struct Sort {
-    /// Source folder to be sorted
-    #[clap(value_parser = dir)]
-    source: Utf8PathBuf,
-    /// Destination to sort the pictures into. If it does not exist, it will be created. Defaults to `source`-sorted
+    /// Source folders to be sorted
+    #[clap(value_parser = dir, required = true)]
+    sources: Vec<Utf8PathBuf>,
+    /// Destination to sort the pictures into. If it does not exist, it will be created. Defaults to the first source with suffix `-sorted`
     #[clap(short, long, value_parser)]
     destination: Option<Utf8PathBuf>,
     /// Do not attempt to open image folders after sorting
     #[clap(short, long, value_parser)]
     no_open: bool,
     #[clap(flatten)]
+    sorting: SortOptions,
+    /// Number of threads used to decode and compare the images. Defaults to the number of CPUs
+    #[clap(short = 'j', long, value_parser)]
+    threads: Option<usize>,
+    /// Maximum memory used by images that are loaded at the same time, e.g. `512M`.
+    /// Accepts the suffixes K, M and G. Unlimited by default
+    #[clap(long, value_parser = size)]
+    memory_budget: Option<u64>,
+    /// Add the images to the piles of an earlier sort in the destination instead of requiring an empty one.
+    /// Images that were sorted before or listed in `failed.txt` are skipped, new images similar to an existing pile
+    /// are linked into its folder and the other new images form new piles. Existing piles are never merged, split
+    /// or renamed. The stats of the run are written to `info-<time>.txt` and `stats-<time>.json`
+    #[clap(short, long, value_parser)]
+    incremental: bool,
+    /// Link files that could not be loaded into a separate `_unreadable` folder.
+    /// They are always listed in `failed.txt`
+    #[clap(long, value_parser)]
+    link_unreadable: bool,
+    #[clap(flatten)]
     options: OpenOptions,
 }
 
+/// Options shared by `sort` and `watch` so that both sort images the same way
+#[derive(Debug, Args)]
+pub struct SortOptions {
+    /// Maximum distance in kilometers between two similar images to be considered duplicates.
+    /// Only applies if both images have a GPS position
+    #[clap(long, value_parser, default_value_t = 1.0)]
+    max_distance: f64,
+    /// Group similar images regardless of how far apart their GPS positions are
+    #[clap(long, value_parser, conflicts_with = "max-distance")]
+    no_max_distance: bool,
+    /// Also recognize rotated and mirrored copies of an image as duplicates. Slows down loading the images
+    #[clap(long, value_parser)]
+    rotation_invariant: bool,
+    /// Hash the thumbnail embedded in JPEGs instead of the full image when its aspect ratio matches.
+    /// Much faster, but misses edits that did not update the thumbnail
+    #[clap(long, value_parser)]
+    exif_thumbnails: bool,
+    /// Compare local features of images with moderately different hashes to also group cropped,
+    /// resized or filtered copies. Slows down loading the images
+    #[clap(long, value_parser)]
+    match_features: bool,
+    /// Also compute DCT and gradient hashes and only consider images similar if
+    /// a majority of the hash algorithms agree. Reduces false merges
+    #[clap(long, value_parser)]
+    multi_hash: bool,
+    /// Number of hash algorithms (out of 3) that have to agree with `--multi-hash`
+    #[clap(long, value_parser = clap::value_parser!(u8).range(1..=3), default_value_t = 2)]
+    min_votes: u8,
+    /// How similar images are combined into piles: `single` puts chains of similar images into one pile,
+    /// `complete` requires every image of a pile to be similar to every other image
+    #[clap(long, value_enum, default_value_t = LinkageArg::Single)]
+    linkage: LinkageArg,
+    /// Split piles with more images than this into smaller piles of closely related images
+    #[clap(long, value_parser)]
+    max_pile_size: Option<usize>,
+    /// Also compare colour signatures so that images differing only in colour (e.g. a black and white edit)
+    /// end up in different piles. Smaller values are stricter, 0.3 is a reasonable start
+    #[clap(long, value_parser)]
+    max_color_distance: Option<f32>,
+    /// Group the piles into an additional level of event folders so they can be reviewed one event at a time
+    #[clap(long, value_parser)]
+    events: bool,
+    /// Minimum time gap in hours between two piles to start a new event with `--events`
+    #[clap(long, value_parser, default_value_t = 12)]
+    event_gap: u32,
+    /// Also start a new event with `--events` when two consecutive piles are further apart than this distance in kilometers
+    #[clap(long, value_parser)]
+    event_distance: Option<f64>,
+    /// Name pile and event folders after the nearest place using a GeoNames-style file (e.g. cities15000.txt).
+    /// Lines are either in the GeoNames dump format or contain the tab separated columns name, latitude and longitude.
+    /// No place data is bundled, the file has to be downloaded (e.g. from https://download.geonames.org/export/dump/) or written by hand
+    #[clap(long, value_parser)]
+    gazetteer: Option<Utf8PathBuf>,
+    /// Maximum distance in kilometers to the nearest place of the `--gazetteer`. Folders of piles further away are not named after a place
+    #[clap(long, value_parser, default_value_t = 50.0, requires = "gazetteer")]
+    gazetteer_max_distance: f64,
+    /// Only load files whose path relative to their source folder matches this glob, e.g. `**/*.jpg`.
+    /// Can be given multiple times
+    #[clap(long, value_parser)]
+    include: Vec<String>,
+    /// Skip files and folders whose path relative to their source folder matches this glob, e.g. `**/@eaDir`.
+    /// Can be given multiple times
+    #[clap(long, value_parser)]
+    exclude: Vec<String>,
+    /// Only load files with one of these extensions, e.g. `jpg,png`
+    #[clap(long, value_parser, use_value_delimiter = true)]
+    extensions: Vec<String>,
+    /// Also load hidden files and folders whose name starts with a dot, e.g. `.thumbnails`
+    #[clap(long, value_parser)]
+    hidden: bool,
+    /// Skip files smaller than this size in bytes. Accepts the suffixes K, M and G
+    #[clap(long, value_parser = size)]
+    min_size: Option<u64>,
+    /// Skip files larger than this size in bytes. Accepts the suffixes K, M and G
+    #[clap(long, value_parser = size)]
+    max_size: Option<u64>,
+    /// Maximum folder depth to descend into below the sources. 1 only loads the files directly inside them
+    #[clap(long, value_parser)]
+    max_depth: Option<usize>,
+    /// Name pile folders after an id derived from the hashes of their images instead of numbering them,
+    /// e.g. `2022-08-14_3fa2c1d0`. The same images always get the same folder name
+    #[clap(long, value_parser)]
+    pile_ids: bool,
+}
+
+impl SortOptions {
+    /// Filters for the source folders. `exclude` is skipped in addition to the globs of `--exclude`
+    pub fn scan_options(&self, exclude: Option<&str>) -> Result<ScanOptions> {
+        let excluded = self.exclude.iter().map(String::as_str).chain(exclude);
+        Ok(ScanOptions {
+            include: glob_set(self.include.iter().map(String::as_str))
+                .wrap_err("Invalid include glob")?,
+            exclude: glob_set(excluded).wrap_err("Invalid exclude glob")?,
+            extensions: self.extensions.clone(),
+            skip_hidden: !self.hidden,
+            min_size: self.min_size,
+            max_size: self.max_size,
+            max_depth: self.max_depth,
+        })
+    }
+
+    pub fn grouping_options(&self) -> GroupingOptions {
+        GroupingOptions {
+            max_distance_km: (!self.no_max_distance).then_some(self.max_distance),
+            feature_matching: self.match_features.then(FeatureMatching::default),
+            voting: self.multi_hash.then(|| HashVoting {
+                min_votes: self.min_votes.into(),
+                ..Default::default()
+            }),
+            linkage: self.linkage.into(),
+            max_color_distance: self.max_color_distance,
+            refinement: self.max_pile_size.map(|max_pile_size| Refinement {
+                max_pile_size,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    pub fn load_options(&self) -> LoadOptions {
+        LoadOptions {
+            rotation_invariant: self.rotation_invariant,
+            features: self.match_features,
+            multi_hash: self.multi_hash,
+            color: self.max_color_distance.is_some(),
+            exif_thumbnails: self.exif_thumbnails,
+            ..Default::default()
+        }
+    }
+
+    pub fn load_gazetteer(&self) -> Result<Option<Gazetteer>> {
+        self.gazetteer
+            .as_deref()
+            .map(Gazetteer::load)
+            .transpose()
+            .wrap_err("Failed to load gazetteer")
+            .map(|gazetteer| {
+                gazetteer.map(|gazetteer| gazetteer.with_max_distance(self.gazetteer_max_distance))
+            })
+    }
+
+    pub fn output_options<'a>(&self, gazetteer: Option<&'a Gazetteer>) -> OutputOptions<'a> {
+        OutputOptions {
+            event_gap: self.events.then(|| Duration::hours(self.event_gap.into())),
+            event_distance_km: self.event_distance,
+            gazetteer,
+            pile_ids: self.pile_ids,
+            ..Default::default()
+        }
+    }
+}
+
+/// Command line values of [`Linkage`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LinkageArg {
+    /// Images are in the same pile if they are connected by a chain of similar images
+    Single,
+    /// Every image of a pile has to be similar to every other image of the pile
+    Complete,
+}
+
+impl From<LinkageArg> for Linkage {
+    fn from(linkage: LinkageArg) -> Self {
+        match linkage {
+            LinkageArg::Single => Linkage::Single,
+            LinkageArg::Complete => Linkage::Complete,
+        }
+    }
+}
+
 impl Sort {
     pub fn run(self) -> Result<()> {
-        let destination = create_dir_from_ref_name(self.destination, &self.source, "sorted")?;
-        let repo = Repository::new(self.source);
-        repo.create_piles(&destination)?;
+        let gazetteer = self.sorting.load_gazetteer()?;
+        let destination = match self.incremental {
+            true => existing_dir_from_ref_name(self.destination, &self.sources[0], "sorted")?,
+            false => create_dir_from_ref_name(self.destination, &self.sources[0], "sorted")?,
+        };
+        let scanning = self.sorting.scan_options(None)?;
+        let grouping = self.sorting.grouping_options();
+        let loading = LoadOptions {
+            memory_budget: self.memory_budget,
+            ..self.sorting.load_options()
+        };
+        if let Some(threads) = self.threads {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build_global()
+                .wrap_err("Failed to set up thread pool")?;
+        }
+        let output = OutputOptions {
+            link_unreadable: self.link_unreadable,
+            ..self.sorting.output_options(gazetteer.as_ref())
+        };
+
+        let start = std::time::Instant::now();
+        let progress = TerminalProgress::new();
+        // an empty destination is sorted into as usual
+        let has_piles = self.incremental && std::fs::read_dir(&destination)?.next().is_some();
+        let existing = match has_piles {
+            true => {
+                let existing = ExistingPiles::load(&destination, &loading, &progress)
+                    .wrap_err_with(|| format!("Failed to load existing piles of {destination}"))?;
+                log_failures(&existing.failures);
+                Some(existing)
+            }
+            false => None,
+        };
+        let mut scan = Scanner::new(self.sources)
+            .scan_options(scanning)
+            .load_options(loading)
+            .skip_files(
+                existing
+                    .as_ref()
+                    .map(|e| e.sorted_sources().clone())
+                    .unwrap_or_default(),
+            )
+            .progress(&progress)
+            .scan();
+        log_failures(&scan.failures);
+        let grouper = Grouper::new(grouping).progress(&progress);
+        let grouping = match existing {
+            Some(ref existing) => {
+                grouper.group_into(&existing.piles, std::mem::take(&mut scan.images))
+            }
+            None => grouper.group(std::mem::take(&mut scan.images)),
+        };
+        let mut stats = Stats::new(&scan, &grouping, start.elapsed());
+        let writing = std::time::Instant::now();
+        let mut writer = PileWriter::new(&destination)
+            .options(output)
+            .stats(&stats)
+            .failures(&scan.failures)
+            .progress(&progress);
+        if let Some(ref existing) = existing {
+            writer = writer.existing(existing, &grouping.attached);
+        }
+        writer
+            .write(&grouping.piles)
+            .wrap_err_with(|| format!("Failed to write piles to {destination}"))?;
+        progress.finish();
+        stats.timings.write_ms = writing.elapsed().as_millis();
+
+        tracing::info!("===== STATS =====");
+        for line in stats.to_string().lines() {
+            tracing::info!("{line}");
+        }
         if !self.no_open {
             Open::new(destination, self.options).run()?;
         };
         Ok(())
     }
 }
+
+fn glob_set<'a>(patterns: impl IntoIterator<Item = &'a str>) -> Result<Option<GlobSet>> {
+    let mut patterns = patterns.into_iter().peekable();
+    if patterns.peek().is_none() {
+        return Ok(None);
+    }
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    Ok(Some(builder.build()?))
+}
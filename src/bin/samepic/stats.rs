@@ -0,0 +1,30 @@
+use camino::Utf8PathBuf;
+use clap::Args;
+use color_eyre::{eyre::Context, Result};
+use samepic::Review;
+
+use crate::common::dir;
+
+/// Shows how far the review of a sorted folder has progressed and how much space deleting the duplicates frees
+#[derive(Debug, Args)]
+pub struct Stats {
+    /// Sorted folder as created by `sort`
+    #[clap(value_parser = dir)]
+    destination: Utf8PathBuf,
+    /// Print the statistics as JSON, including every pile
+    #[clap(long, value_parser)]
+    json: bool,
+}
+
+impl Stats {
+    pub fn run(self) -> Result<()> {
+        let review = Review::load(&self.destination)
+            .wrap_err_with(|| format!("Failed to inspect {}", self.destination))?;
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&review)?);
+        } else {
+            print!("{review}");
+        }
+        Ok(())
+    }
+}
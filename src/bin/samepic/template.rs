@@ -0,0 +1,249 @@
+use std::fmt::Write;
+
+use camino::Utf8Path;
+use chrono::format::{Item, StrftimeItems};
+use chrono::NaiveDate;
+use color_eyre::{
+    eyre::{bail, eyre},
+    Help, Result,
+};
+use samepic::{sanitize_file_name, Image, ImageData, DATETIME_FORMATTER};
+
+/// Placeholders that may be used in a [`Template`]
+const PLACEHOLDERS: &str = "{date}, {date:<strftime format>}, {camera}, {orig_stem}, {pile}, \
+                            {seq}, {hash8}, {ext}, {ext_lower}";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Date(String),
+    Camera,
+    OrigStem,
+    Pile,
+    Seq,
+    Hash8,
+    Ext,
+    ExtLower,
+}
+
+/// File name template for collected images, e.g. `{date:%Y%m%d}_{camera}_{seq}.{ext_lower}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+/// Everything a [`Template`] may refer to when rendering the name of a single image
+pub struct TemplateContext<'a> {
+    pub original: &'a Utf8Path,
+    pub pile: &'a str,
+    pub seq: usize,
+    pub data: &'a ImageData,
+    pub image: Option<&'a Image>,
+}
+
+impl Template {
+    pub fn timestamp() -> Self {
+        Self {
+            segments: vec![
+                Segment::Date(DATETIME_FORMATTER.to_owned()),
+                Segment::Literal(".".to_owned()),
+                Segment::Ext,
+            ],
+        }
+    }
+
+    pub fn original_name() -> Self {
+        Self {
+            segments: vec![
+                Segment::OrigStem,
+                Segment::Literal(".".to_owned()),
+                Segment::Ext,
+            ],
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| eyre!("Unclosed placeholder in template {s}"))?;
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Self::parse_placeholder(&rest[..end])?);
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => bail!("Unmatched '}}' in template {s}"),
+                '/' | '\\' => {
+                    bail!("Template {s} must not contain path separators")
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        if segments.is_empty() {
+            bail!("Template must not be empty");
+        }
+        if segments.iter().all(|s| matches!(s, Segment::Literal(_))) {
+            return Err(eyre!("Template {s} does not contain any placeholder"))
+                .suggestion(format!("Available placeholders: {PLACEHOLDERS}"));
+        }
+
+        Ok(Self { segments })
+    }
+
+    fn parse_placeholder(placeholder: &str) -> Result<Segment> {
+        let segment = match placeholder.split_once(':') {
+            Some(("date", format)) => {
+                if format.is_empty()
+                    || StrftimeItems::new(format).any(|item| matches!(item, Item::Error))
+                {
+                    bail!("Invalid date format {format:?} in placeholder {{{placeholder}}}");
+                }
+                // formats like %D or %x render slashes, which would create folders
+                let sample = NaiveDate::from_ymd(2000, 1, 1)
+                    .and_hms(0, 0, 0)
+                    .format(format)
+                    .to_string();
+                if sample.contains(['/', '\\']) {
+                    return Err(eyre!(
+                        "Date format {format:?} in placeholder {{{placeholder}}} contains a path separator"
+                    ))
+                    .suggestion("Use - or _ to separate the date components");
+                }
+                Segment::Date(format.to_owned())
+            }
+            Some(_) => {
+                return Err(eyre!("Unknown placeholder {{{placeholder}}}"))
+                    .suggestion(format!("Available placeholders: {PLACEHOLDERS}"))
+            }
+            None => match placeholder {
+                "date" => Segment::Date(DATETIME_FORMATTER.to_owned()),
+                "camera" => Segment::Camera,
+                "orig_stem" => Segment::OrigStem,
+                "pile" => Segment::Pile,
+                "seq" => Segment::Seq,
+                "hash8" => Segment::Hash8,
+                "ext" => Segment::Ext,
+                "ext_lower" => Segment::ExtLower,
+                _ => {
+                    return Err(eyre!("Unknown placeholder {{{placeholder}}}"))
+                        .suggestion(format!("Available placeholders: {PLACEHOLDERS}"))
+                }
+            },
+        };
+        Ok(segment)
+    }
+
+    /// Whether rendering requires the decoded image, i.e. [`TemplateContext::image`]
+    pub fn needs_image(&self) -> bool {
+        self.segments.contains(&Segment::Hash8)
+    }
+
+    pub fn render(&self, context: &TemplateContext) -> Result<String> {
+        let TemplateContext {
+            original,
+            pile,
+            seq,
+            data,
+            image,
+        } = context;
+        let extension = || {
+            original
+                .extension()
+                .ok_or_else(|| eyre!("Missing file extension for path {original}"))
+        };
+
+        let mut name = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => name.push_str(literal),
+                Segment::Date(format) => write!(name, "{}", data.timestamp.format(format))?,
+                Segment::Camera => {
+                    let camera = data.camera.as_deref().unwrap_or("unknown");
+                    name.push_str(&sanitize_file_name(camera));
+                }
+                Segment::OrigStem => name.push_str(
+                    original
+                        .file_stem()
+                        .ok_or_else(|| eyre!("Invalid file stem for path {original}"))?,
+                ),
+                Segment::Pile => name.push_str(&sanitize_file_name(pile)),
+                Segment::Seq => write!(name, "{seq:04}")?,
+                Segment::Hash8 => {
+                    let image = image.ok_or_else(|| eyre!("Missing image hash for {original}"))?;
+                    for byte in image.hash().as_bytes().iter().take(4) {
+                        write!(name, "{byte:02x}")?;
+                    }
+                }
+                Segment::Ext => name.push_str(extension()?),
+                Segment::ExtLower => name.push_str(&extension()?.to_lowercase()),
+            }
+        }
+        Ok(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_placeholders() {
+        let template = Template::parse("{date:%Y-%m-%d}_{{{seq}}}.{ext_lower}").unwrap();
+        assert_eq!(
+            template.segments,
+            [
+                Segment::Date("%Y-%m-%d".to_owned()),
+                Segment::Literal("_{".to_owned()),
+                Segment::Seq,
+                Segment::Literal("}.".to_owned()),
+                Segment::ExtLower,
+            ]
+        );
+    }
+
+    #[test]
+    fn reject_invalid_templates() {
+        for template in [
+            "",
+            "name",
+            "{date",
+            "date}",
+            "{unknown}",
+            "{camera:x}",
+            "{date:}",
+            "{date:%Q}",
+            "{pile}/{seq}",
+            "{pile}\\{seq}",
+        ] {
+            assert!(Template::parse(template).is_err(), "accepted {template:?}");
+        }
+    }
+
+    #[test]
+    fn reject_date_formats_with_path_separators() {
+        for format in ["%Y/%m", "%D", "%x", "%F\\%T"] {
+            let template = format!("{{date:{format}}}");
+            assert!(Template::parse(&template).is_err(), "accepted {template:?}");
+        }
+        assert!(Template::parse("{date:%Y-%m-%d_%H%M%S}").is_ok());
+    }
+}
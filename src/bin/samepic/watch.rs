@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::Args;
+use color_eyre::{eyre::Context, Result};
+use notify::event::{AccessKind, AccessMode};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use samepic::{
+    ExistingPiles, Grouper, Image, ImageLoadError, LoadFailure, NoProgress, OutputOptions,
+    PileWriter, Scanner,
+};
+
+use crate::common::{dir, existing_dir_from_ref_name, log_failures};
+use crate::sort::SortOptions;
+
+/// Watches a folder and sorts new images into the piles of a destination as soon as they are written
+#[derive(Debug, Args)]
+pub struct Watch {
+    /// Folder to watch, including its subfolders
+    #[clap(value_parser = dir)]
+    source: Utf8PathBuf,
+    /// Destination to sort the pictures into. It may contain the piles of an earlier `sort` or `watch`.
+    /// Defaults to the source with suffix `-sorted`
+    #[clap(short, long, value_parser)]
+    destination: Option<Utf8PathBuf>,
+    /// Seconds without further writes after which a new file is considered complete
+    #[clap(long, value_parser, default_value_t = 2.0)]
+    settle: f64,
+    #[clap(flatten)]
+    sorting: SortOptions,
+}
+
+impl Watch {
+    pub fn run(self) -> Result<()> {
+        let gazetteer = self.sorting.load_gazetteer()?;
+        let destination = existing_dir_from_ref_name(self.destination, &self.source, "sorted")?;
+        // file events report absolute paths, which the scan filters need relative to the source
+        let source = self.source.canonicalize_utf8()?;
+        let ignored = destination.canonicalize_utf8()?;
+        let settle = Duration::from_secs_f64(self.settle);
+        let loading = self.sorting.load_options();
+        let grouper = Grouper::new(self.sorting.grouping_options());
+        let output = self.sorting.output_options(gazetteer.as_ref());
+
+        let mut existing = match std::fs::read_dir(&destination)?.next() {
+            Some(_) => ExistingPiles::load(&destination, &loading, &NoProgress)
+                .wrap_err_with(|| format!("Failed to load existing piles of {destination}"))?,
+            None => ExistingPiles::default(),
+        };
+        log_failures(&existing.failures);
+
+        // images that arrived while nobody was watching, without the links of a destination inside the source
+        let destination_glob = ignored
+            .strip_prefix(&source)
+            .ok()
+            .map(|relative| globset::escape(relative.as_str()));
+        let scanning = self.sorting.scan_options(destination_glob.as_deref())?;
+        let scan = Scanner::new([&source])
+            .scan_options(scanning.clone())
+            .load_options(loading.clone())
+            .skip_files(existing.sorted_sources().clone())
+            .scan();
+        log_failures(&scan.failures);
+        place(
+            &destination,
+            &grouper,
+            &output,
+            &mut existing,
+            scan.images,
+            &scan.failures,
+        )?;
+
+        let (sender, receiver) = channel();
+        let mut watcher =
+            notify::recommended_watcher(sender).wrap_err("Failed to set up file watcher")?;
+        watcher
+            .watch(source.as_std_path(), RecursiveMode::Recursive)
+            .wrap_err_with(|| format!("Failed to watch {source}"))?;
+        tracing::info!("Watching {source} for new images.");
+
+        // files that were written to, by the time of their last write
+        let mut pending: HashMap<Utf8PathBuf, Instant> = HashMap::new();
+        loop {
+            match receiver.recv_timeout(settle) {
+                Ok(event) => {
+                    let event = event.wrap_err("Failed to watch for new images")?;
+                    for path in written_files(event) {
+                        pending.insert(path, Instant::now());
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+
+            let settled: Vec<_> = pending
+                .iter()
+                .filter(|(_, written)| written.elapsed() >= settle)
+                .map(|(path, _)| path.clone())
+                .collect();
+            let mut images = Vec::with_capacity(settled.len());
+            let mut failures = Vec::new();
+            for path in settled {
+                pending.remove(&path);
+                let Ok(canonical) = path.canonicalize_utf8() else {
+                    // deleted again before it settled
+                    continue;
+                };
+                // the size filters only apply once the file is complete
+                if canonical.starts_with(&ignored)
+                    || existing.sorted_sources().contains(&canonical)
+                    || !scanning.accepts_path(&source, &path)
+                {
+                    continue;
+                }
+                match Image::load_with_options(&path, &loading) {
+                    Ok(image) => images.push(image),
+                    Err(ImageLoadError::NotAnImage(kind)) => {
+                        tracing::debug!("Skipped {path}: file type {kind} is not an image");
+                    }
+                    Err(error) => failures.push(LoadFailure { path, error }),
+                }
+            }
+            log_failures(&failures);
+            place(
+                &destination,
+                &grouper,
+                &output,
+                &mut existing,
+                images,
+                &failures,
+            )?;
+        }
+    }
+}
+
+/// Regular files that were created or written to by `event`
+fn written_files(event: Event) -> impl Iterator<Item = Utf8PathBuf> {
+    let written = matches!(
+        event.kind,
+        EventKind::Create(_)
+            | EventKind::Modify(_)
+            | EventKind::Access(AccessKind::Close(AccessMode::Write))
+    );
+    event
+        .paths
+        .into_iter()
+        .filter(move |_| written)
+        .filter_map(|path| Utf8PathBuf::try_from(path).ok())
+        .filter(|path| path.is_file())
+}
+
+/// Groups `images` into the existing piles and links them into the destination.
+/// The `failures` are listed in `failed.txt` so that they are not retried
+fn place(
+    destination: &Utf8Path,
+    grouper: &Grouper,
+    output: &OutputOptions,
+    existing: &mut ExistingPiles,
+    images: Vec<Image>,
+    failures: &[LoadFailure],
+) -> Result<()> {
+    if images.is_empty() && failures.is_empty() {
+        return Ok(());
+    }
+    let grouping = grouper.group_into(&existing.piles, images);
+    let dirs = PileWriter::new(destination)
+        .options(output.clone())
+        .failures(failures)
+        .existing(existing, &grouping.attached)
+        .write(&grouping.piles)
+        .wrap_err_with(|| format!("Failed to write piles to {destination}"))?;
+    for (index, pile) in &grouping.attached {
+        tracing::info!("Added {} images to {}", pile.len(), existing.dirs[*index]);
+    }
+    for (dir, pile) in dirs.iter().zip(&grouping.piles) {
+        tracing::info!("Created {dir} with {} images", pile.len());
+    }
+    existing.add(grouping, dirs);
+    existing.add_failures(failures);
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use std::sync::{Condvar, Mutex};
+
+/// Limits the memory used by images that are loaded at the same time
+pub(crate) struct MemoryBudget {
+    limit: u64,
+    used: Mutex<u64>,
+    released: Condvar,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            used: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Blocks until `amount` bytes fit into the budget and reserves them.
+    ///
+    /// Succeeds immediately if nothing is reserved so that images larger than the whole budget can still be loaded.
+    pub fn acquire(&self, amount: u64) {
+        self.acquire_more(0, amount);
+    }
+
+    /// Like [`MemoryBudget::acquire`] for a caller that already reserved `held` bytes.
+    ///
+    /// Succeeds immediately if nothing but those bytes is reserved, so the caller cannot wait for itself.
+    pub fn acquire_more(&self, held: u64, amount: u64) {
+        let mut used = self.used.lock().expect("memory budget lock poisoned");
+        while *used > held && used.saturating_add(amount) > self.limit {
+            used = self
+                .released
+                .wait(used)
+                .expect("memory budget lock poisoned");
+        }
+        *used = used.saturating_add(amount);
+    }
+
+    pub fn release(&self, amount: u64) {
+        let mut used = self.used.lock().expect("memory budget lock poisoned");
+        *used = used.saturating_sub(amount);
+        self.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    use super::*;
+
+    fn used(budget: &MemoryBudget) -> u64 {
+        *budget.used.lock().unwrap()
+    }
+
+    #[test]
+    fn acquire_and_release() {
+        let budget = MemoryBudget::new(100);
+        budget.acquire(60);
+        budget.acquire(40);
+        assert_eq!(used(&budget), 100);
+        budget.release(100);
+        // larger than the whole budget, but nothing else is loaded
+        budget.acquire(500);
+        assert_eq!(used(&budget), 500);
+        // the caller holds everything that is reserved, so it must not wait for itself
+        budget.acquire_more(500, 10);
+        assert_eq!(used(&budget), 510);
+        budget.release(510);
+        assert_eq!(used(&budget), 0);
+    }
+
+    #[test]
+    fn wait_for_release() {
+        let budget = MemoryBudget::new(100);
+        let acquired = AtomicBool::new(false);
+        budget.acquire(60);
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                budget.acquire(60);
+                acquired.store(true, Ordering::SeqCst);
+            });
+            std::thread::sleep(Duration::from_millis(100));
+            assert!(!acquired.load(Ordering::SeqCst));
+            budget.release(60);
+        });
+        assert!(acquired.load(Ordering::SeqCst));
+        assert_eq!(used(&budget), 60);
+    }
+}
@@ -0,0 +1,72 @@
+use image::DynamicImage;
+
+/// Edge length of the thumbnail the colour moments are computed on
+const SIGNATURE_SIZE: u32 = 64;
+
+/// Colour moments of an image: mean, standard deviation and skewness of each RGB channel.
+///
+/// Unlike the perceptual hashes, which only look at brightness, this distinguishes a black
+/// and white edit from its colour original or two differently white-balanced shots.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorSignature {
+    /// Moments per channel, normalized to the range of 0 to 1
+    moments: [[f32; 3]; 3],
+}
+
+impl ColorSignature {
+    pub fn from_image(image: &DynamicImage) -> Self {
+        let thumbnail = image.thumbnail(SIGNATURE_SIZE, SIGNATURE_SIZE).to_rgb8();
+        let count = thumbnail.pixels().len().max(1) as f32;
+
+        let mut moments = [[0.0; 3]; 3];
+        for channel in 0..3 {
+            let values = || thumbnail.pixels().map(|p| p[channel] as f32 / 255.0);
+            let mean = values().sum::<f32>() / count;
+            let variance = values().map(|v| (v - mean).powi(2)).sum::<f32>() / count;
+            let skewness = values().map(|v| (v - mean).powi(3)).sum::<f32>() / count;
+            moments[channel] = [mean, variance.sqrt(), skewness.cbrt()];
+        }
+        Self { moments }
+    }
+
+    /// Sum of the absolute differences of all moments
+    pub fn distance(&self, other: &ColorSignature) -> f32 {
+        self.moments
+            .iter()
+            .flatten()
+            .zip(other.moments.iter().flatten())
+            .map(|(l, r)| (l - r).abs())
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{imageops::FilterType, Rgb, RgbImage};
+
+    use super::*;
+
+    fn colorful() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(200, 150, |x, y| {
+            Rgb([(x * 255 / 200) as u8, (y * 255 / 150) as u8, 60])
+        }))
+    }
+
+    #[test]
+    fn tell_colour_edits_apart() {
+        let image = colorful();
+        let signature = ColorSignature::from_image(&image);
+        assert_eq!(signature.distance(&signature), 0.0);
+
+        let resized = ColorSignature::from_image(&image.resize(100, 75, FilterType::Triangle));
+        // well below the 0.3 the CLI suggests as a start for `--max-color-distance`
+        let distance = signature.distance(&resized);
+        assert!(distance < 0.15, "distance {distance}");
+
+        // the grayscale edit has the same brightness but no colours
+        let gray = ColorSignature::from_image(&DynamicImage::ImageLuma8(image.to_luma8()));
+        let distance = signature.distance(&gray);
+        assert!(distance > 0.3, "distance {distance}");
+        assert_eq!(distance, gray.distance(&signature));
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::HashSet;
+
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::image::Image;
+
+/// Differences between two image libraries
+#[derive(Debug, Default)]
+pub struct Diff<'a> {
+    /// Images of the left library without a near-duplicate in the right library
+    pub only_left: Vec<&'a Image>,
+    /// Images of the right library without a near-duplicate in the left library
+    pub only_right: Vec<&'a Image>,
+    /// Each image of the right library with a near-duplicate in the left library,
+    /// together with its closest left image and their hash distance
+    pub duplicates: Vec<(&'a Image, &'a Image, u32)>,
+}
+
+/// Compares two image libraries and reports which images are only in one of them.
+///
+/// Two images are considered near-duplicates if their hash distance is below `max_distance`,
+/// like in [`GroupingOptions::max_hash_distance`](crate::GroupingOptions::max_hash_distance).
+/// All lists of the result are ordered by path.
+pub fn diff<'a>(
+    left: impl IntoIterator<Item = &'a Image>,
+    right: impl IntoIterator<Item = &'a Image>,
+    max_distance: u32,
+) -> Diff<'a> {
+    let left: Vec<_> = left.into_iter().collect();
+    let right: Vec<_> = right.into_iter().collect();
+    let matches: Vec<_> = right
+        .par_iter()
+        .map(|&r| {
+            let similar: Vec<_> = left
+                .iter()
+                .map(|&l| (l, l.hash_distance(r)))
+                .filter(|&(_, distance)| distance < max_distance)
+                .collect();
+            (r, similar)
+        })
+        .collect();
+
+    let matched_left: HashSet<_> = matches
+        .iter()
+        .flat_map(|(_, similar)| similar.iter().map(|(l, _)| l.path()))
+        .collect();
+
+    let mut diff = Diff {
+        only_left: left
+            .into_iter()
+            .filter(|l| !matched_left.contains(l.path()))
+            .collect(),
+        ..Default::default()
+    };
+    for (r, similar) in matches {
+        match similar
+            .into_iter()
+            .min_by_key(|&(l, distance)| (distance, l.path()))
+        {
+            Some((l, distance)) => diff.duplicates.push((l, r, distance)),
+            None => diff.only_right.push(r),
+        }
+    }
+
+    diff.only_left.sort_by_key(|image| image.path());
+    diff.only_right.sort_by_key(|image| image.path());
+    diff.duplicates.sort_by_key(|(_, r, _)| r.path());
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{temp_dir, test_image};
+
+    #[test]
+    fn classify_images() {
+        let dir = temp_dir("diff");
+        std::fs::create_dir_all(dir.join("left")).unwrap();
+        std::fs::create_dir_all(dir.join("right")).unwrap();
+        let left = [
+            test_image(&dir, "left/a.png", 4),
+            test_image(&dir, "left/b.png", 8),
+        ];
+        let right = [
+            test_image(&dir, "right/a-copy.png", 4),
+            test_image(&dir, "right/c.png", 2),
+        ];
+        let paths = |images: &[&Image]| {
+            images
+                .iter()
+                .map(|image| image.path().to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        let diff = super::diff(&left, &right, 1);
+        assert_eq!(paths(&diff.only_left), [dir.join("left/b.png")]);
+        assert_eq!(paths(&diff.only_right), [dir.join("right/c.png")]);
+        assert_eq!(diff.duplicates.len(), 1);
+        let (l, r, distance) = diff.duplicates[0];
+        assert_eq!(
+            (l.path(), r.path(), distance),
+            (left[0].path(), right[0].path(), 0)
+        );
+
+        // with an unlimited distance every image has a near-duplicate
+        let diff = super::diff(&left, &right, u32::MAX);
+        assert!(diff.only_left.is_empty() && diff.only_right.is_empty());
+        assert_eq!(diff.duplicates.len(), 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
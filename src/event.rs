@@ -0,0 +1,162 @@
+use chrono::{Duration, NaiveDateTime};
+
+use crate::location::Location;
+use crate::pile::Pile;
+
+/// Consecutive piles that were taken close to each other in time, e.g. a day trip or a party
+#[derive(Debug, Clone)]
+pub struct Event<'a> {
+    /// Indices of the piles in the slice passed to [`cluster_events`], ordered by time
+    pub piles: Vec<usize>,
+    all_piles: &'a [Pile],
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    last_location: Option<Location>,
+}
+
+impl<'a> Event<'a> {
+    fn new(all_piles: &'a [Pile], index: usize) -> Self {
+        let pile = &all_piles[index];
+        let (start, end) = pile.time_range();
+        Event {
+            piles: vec![index],
+            all_piles,
+            start,
+            end,
+            last_location: pile.location(),
+        }
+    }
+
+    fn push(&mut self, index: usize) {
+        let pile = &self.all_piles[index];
+        let (start, end) = pile.time_range();
+        self.start = self.start.min(start);
+        self.end = self.end.max(end);
+        self.last_location = pile.location().or(self.last_location);
+        self.piles.push(index);
+    }
+
+    pub fn start(&self) -> NaiveDateTime {
+        self.start
+    }
+
+    pub fn end(&self) -> NaiveDateTime {
+        self.end
+    }
+
+    /// Average GPS position of all piles in the event that have one
+    pub fn location(&self) -> Option<Location> {
+        let locations: Vec<_> = self
+            .piles
+            .iter()
+            .filter_map(|&index| self.all_piles[index].location())
+            .collect();
+        Location::centroid(&locations)
+    }
+
+    fn is_far_from(&self, pile: &Pile, max_distance_km: Option<f64>) -> bool {
+        match (max_distance_km, self.last_location, pile.location()) {
+            (Some(max), Some(l), Some(r)) => l.distance_km(&r) > max,
+            _ => false,
+        }
+    }
+
+    /// Date range of the event, e.g. `2022-07-14` or `2022-07-14_2022-07-16`
+    pub fn name(&self) -> String {
+        let (start, end) = (self.start.date(), self.end.date());
+        if start == end {
+            start.to_string()
+        } else {
+            format!("{start}_{end}")
+        }
+    }
+}
+
+/// Groups piles into events. A new event starts whenever the time between the
+/// last image of the previous pile and the first image of the next pile exceeds `max_gap`
+/// or, if both have a GPS position, their distance exceeds `max_distance_km`.
+pub fn cluster_events(
+    piles: &[Pile],
+    max_gap: Duration,
+    max_distance_km: Option<f64>,
+) -> Vec<Event<'_>> {
+    let mut sorted: Vec<_> = (0..piles.len()).collect();
+    sorted.sort_by_key(|&index| piles[index].time_range());
+
+    let mut events: Vec<Event> = Vec::new();
+    for index in sorted {
+        let pile = &piles[index];
+        match events.last_mut() {
+            Some(event)
+                if pile.time_range().0 - event.end <= max_gap
+                    && !event.is_far_from(pile, max_distance_km) =>
+            {
+                event.push(index)
+            }
+            _ => events.push(Event::new(piles, index)),
+        }
+    }
+
+    tracing::debug!(
+        "Clustered {} piles into {} events",
+        piles.len(),
+        events.len()
+    );
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{temp_dir, test_image};
+
+    #[test]
+    fn split_events_at_gaps_and_distances() {
+        let dir = temp_dir("event-cluster");
+        let start = NaiveDateTime::parse_from_str("2022-07-14 10:00", "%Y-%m-%d %H:%M").unwrap();
+        let lisbon = Location {
+            latitude: 38.7167,
+            longitude: -9.1333,
+            altitude: None,
+        };
+        let porto = Location {
+            latitude: 41.15,
+            longitude: -8.61,
+            altitude: None,
+        };
+        // hours after the start and position of each pile, not in chronological order
+        let piles: Vec<_> = [
+            (1, None),
+            (0, Some(lisbon)),
+            (20, Some(lisbon)),
+            (21, Some(porto)),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, (hours, location))| {
+            let mut image = test_image(&dir, &format!("{i}.png"), 4);
+            image.timestamp = start + Duration::hours(hours);
+            image.location = location;
+            Pile::new(image)
+        })
+        .collect();
+
+        let events = cluster_events(&piles, Duration::hours(12), None);
+        let indices: Vec<_> = events.iter().map(|event| event.piles.clone()).collect();
+        assert_eq!(indices, [vec![1, 0], vec![2, 3]]);
+        assert_eq!(events[0].name(), "2022-07-14");
+        assert_eq!(events[1].name(), "2022-07-15");
+        // the pile without a position does not count
+        let location = events[0].location().unwrap();
+        assert!(location.distance_km(&lisbon) < 0.001);
+
+        let events = cluster_events(&piles, Duration::hours(12), Some(100.0));
+        let indices: Vec<_> = events.iter().map(|event| event.piles.clone()).collect();
+        assert_eq!(indices, [vec![1, 0], vec![2], vec![3]]);
+
+        let events = cluster_events(&piles, Duration::hours(24), None);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].name(), "2022-07-14_2022-07-15");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::{HashMap, HashSet};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use thiserror::Error;
+
+use crate::grouper::Grouping;
+use crate::image::{Image, LoadOptions};
+use crate::manifest::{Manifest, ManifestError, MANIFEST_FILE};
+use crate::pile::Pile;
+use crate::progress::Progress;
+use crate::scanner::{LoadFailure, ScanOptions, Scanner};
+use crate::writer::{FAILURES_FILE, UNREADABLE_PILE};
+
+/// Piles of a destination that was written by an earlier run of the [`PileWriter`](crate::PileWriter).
+///
+/// The piles are loaded from their folders as they are now, so images deleted while reviewing
+/// stay deleted. The manifest tells which source images were already sorted.
+#[derive(Debug, Default)]
+pub struct ExistingPiles {
+    /// Pile folders in the same order as `piles`
+    pub dirs: Vec<Utf8PathBuf>,
+    pub piles: Vec<Pile>,
+    /// Images in the pile folders that could not be loaded
+    pub failures: Vec<LoadFailure>,
+    sorted_sources: HashSet<Utf8PathBuf>,
+}
+
+#[derive(Debug, Error)]
+pub enum ExistingPilesError {
+    #[error("{0} not found, the destination was not created by sort")]
+    MissingManifest(Utf8PathBuf),
+    #[error(transparent)]
+    Manifest(#[from] ManifestError),
+    #[error("failed to read pile folders of {0}")]
+    ReadDir(Utf8PathBuf, #[source] std::io::Error),
+    #[error("failed to read {0}")]
+    ReadFailures(Utf8PathBuf, #[source] std::io::Error),
+}
+
+impl ExistingPiles {
+    pub fn load(
+        dest: &Utf8Path,
+        load_options: &LoadOptions,
+        progress: &dyn Progress,
+    ) -> Result<Self, ExistingPilesError> {
+        let manifest_path = dest.join(MANIFEST_FILE);
+        if !manifest_path.exists() {
+            return Err(ExistingPilesError::MissingManifest(manifest_path));
+        }
+        let manifest = Manifest::load(&manifest_path)?;
+        let dirs =
+            pile_dirs(dest).map_err(|err| ExistingPilesError::ReadDir(dest.to_owned(), err))?;
+
+        let scan = Scanner::new(dirs.iter().cloned())
+            .scan_options(ScanOptions {
+                max_depth: Some(1),
+                ..Default::default()
+            })
+            .load_options(load_options.clone())
+            .progress(progress)
+            .scan();
+        let mut images: HashMap<Utf8PathBuf, Vec<Image>> = HashMap::new();
+        for image in scan.images {
+            let dir = image.path().parent().unwrap_or(dest).to_owned();
+            images.entry(dir).or_default().push(image);
+        }
+        // piles whose images were all deleted during the review are gone
+        let (dirs, piles) = dirs
+            .into_iter()
+            .filter_map(|dir| {
+                let mut images = images.remove(&dir)?.into_iter();
+                let mut pile = Pile::new(images.next()?);
+                pile.extend(images);
+                Some((dir, pile))
+            })
+            .unzip();
+
+        // files that failed before are not retried, so they are not linked into the unreadable pile again
+        let failures_path = dest.join(FAILURES_FILE);
+        let failed_sources = read_failed_sources(&failures_path)
+            .map_err(|err| ExistingPilesError::ReadFailures(failures_path, err))?;
+        let sorted_sources = manifest
+            .entries
+            .iter()
+            .map(|entry| &entry.source)
+            .chain(&failed_sources)
+            .filter_map(|source| source.canonicalize_utf8().ok())
+            .collect();
+        tracing::debug!("Loaded existing piles from {dest}");
+        Ok(Self {
+            dirs,
+            piles,
+            failures: scan.failures,
+            sorted_sources,
+        })
+    }
+
+    /// Adds the piles of a [`Grouping`] made with [`Grouper::group_into`](crate::Grouper::group_into)
+    /// after the [`PileWriter`](crate::PileWriter) wrote them into `dirs`
+    pub fn add(&mut self, grouping: Grouping, dirs: Vec<Utf8PathBuf>) {
+        let images = grouping
+            .piles
+            .iter()
+            .chain(grouping.attached.iter().map(|(_, pile)| pile))
+            .flat_map(|pile| &pile.pictures);
+        self.sorted_sources
+            .extend(images.filter_map(|image| image.path().canonicalize_utf8().ok()));
+        for (index, pile) in grouping.attached {
+            self.piles[index].merge(pile);
+        }
+        self.dirs.extend(dirs);
+        self.piles.extend(grouping.piles);
+    }
+
+    /// Adds the files that could not be loaded after the [`PileWriter`](crate::PileWriter) listed them in `failed.txt`,
+    /// so that they are not retried either
+    pub fn add_failures(&mut self, failures: &[LoadFailure]) {
+        self.sorted_sources.extend(
+            failures
+                .iter()
+                .filter_map(|failure| failure.path.canonicalize_utf8().ok()),
+        );
+    }
+
+    /// Canonical paths of all source images that were sorted before, including those deleted while reviewing
+    /// and those that could not be loaded
+    pub fn sorted_sources(&self) -> &HashSet<Utf8PathBuf> {
+        &self.sorted_sources
+    }
+}
+
+/// Files listed in the failures file at `path`, if there is one
+fn read_failed_sources(path: &Utf8Path) -> std::io::Result<Vec<Utf8PathBuf>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    // the first line is the header
+    Ok(content
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split('\t').next())
+        .filter(|file| !file.is_empty())
+        .map(Utf8PathBuf::from)
+        .collect())
+}
+
+/// All folders below `dest` that do not contain other folders, i.e. the piles without their event folders.
+/// The [`UNREADABLE_PILE`] is not a pile
+pub(crate) fn pile_dirs(dest: &Utf8Path) -> std::io::Result<Vec<Utf8PathBuf>> {
+    fn visit(dir: &Utf8Path, top_level: bool, dirs: &mut Vec<Utf8PathBuf>) -> std::io::Result<()> {
+        let mut subdirs = Vec::new();
+        for entry in dir.read_dir_utf8()? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() && !(top_level && entry.file_name() == UNREADABLE_PILE) {
+                subdirs.push(entry.path().to_owned());
+            }
+        }
+        if subdirs.is_empty() && !top_level {
+            dirs.push(dir.to_owned());
+        }
+        subdirs.sort_unstable();
+        for subdir in subdirs {
+            visit(&subdir, false, dirs)?;
+        }
+        Ok(())
+    }
+
+    let mut dirs = Vec::new();
+    visit(dest, true, &mut dirs)?;
+    Ok(dirs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grouper::{Grouper, GroupingOptions};
+    use crate::progress::NoProgress;
+    use crate::testing::{temp_dir, test_image};
+    use crate::writer::PileWriter;
+
+    #[test]
+    fn attach_near_duplicates_to_existing_piles() {
+        let root = temp_dir("incremental");
+        let (src, dest) = (root.join("src"), root.join("dest"));
+        std::fs::create_dir(&src).unwrap();
+        std::fs::create_dir(&dest).unwrap();
+        let grouper = Grouper::new(GroupingOptions::default());
+        let grouping = grouper.group(vec![test_image(&src, "a.png", 4)]);
+        PileWriter::new(&dest).write(&grouping.piles).unwrap();
+
+        let mut existing =
+            ExistingPiles::load(&dest, &LoadOptions::default(), &NoProgress).unwrap();
+        assert_eq!(existing.piles.len(), 1);
+        assert!(existing
+            .sorted_sources()
+            .contains(&src.join("a.png").canonicalize_utf8().unwrap()));
+
+        let duplicate = test_image(&src, "a-copy.png", 4);
+        let new = test_image(&src, "b.png", 16);
+        let grouping = grouper.group_into(&existing.piles, vec![duplicate, new]);
+        assert_eq!(grouping.attached.len(), 1);
+        assert_eq!(grouping.attached[0].0, 0);
+        assert_eq!(
+            grouping.attached[0].1.first().path(),
+            src.join("a-copy.png")
+        );
+        assert_eq!(grouping.piles.len(), 1);
+        assert_eq!(grouping.piles[0].first().path(), src.join("b.png"));
+
+        let dirs = PileWriter::new(&dest)
+            .existing(&existing, &grouping.attached)
+            .write(&grouping.piles)
+            .unwrap();
+        existing.add(grouping, dirs);
+        assert_eq!(existing.piles.len(), 2);
+        assert_eq!(existing.piles[0].len(), 2);
+        assert_eq!(existing.sorted_sources().len(), 3);
+
+        // a reload sees the same piles
+        let reloaded = ExistingPiles::load(&dest, &LoadOptions::default(), &NoProgress).unwrap();
+        let mut sizes: Vec<_> = reloaded.piles.iter().map(Pile::len).collect();
+        sizes.sort_unstable();
+        assert_eq!(sizes, [1, 2]);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn pile_dirs_skip_unreadable_pile() {
+        let dest = temp_dir("pile-dirs");
+        for dir in [
+            "2022-08-14_0000",
+            "2022-08-15/2022-08-15_0000",
+            "2022-08-15/2022-08-15_0001",
+            UNREADABLE_PILE,
+        ] {
+            std::fs::create_dir_all(dest.join(dir)).unwrap();
+        }
+        std::fs::write(dest.join(MANIFEST_FILE), "").unwrap();
+
+        let dirs = pile_dirs(&dest).unwrap();
+        let relative: Vec<_> = dirs
+            .iter()
+            .map(|dir| dir.strip_prefix(&dest).unwrap().as_str())
+            .collect();
+        assert_eq!(
+            relative,
+            [
+                "2022-08-14_0000",
+                "2022-08-15/2022-08-15_0000",
+                "2022-08-15/2022-08-15_0001"
+            ]
+        );
+        std::fs::remove_dir_all(dest).unwrap();
+    }
+}
@@ -0,0 +1,380 @@
+use image::{imageops::FilterType, DynamicImage, GrayImage};
+
+/// Maximum edge length of the first pyramid level
+const BASE_SIZE: u32 = 512;
+const PYRAMID_LEVELS: usize = 4;
+const PYRAMID_SCALE: f32 = std::f32::consts::SQRT_2;
+/// Keypoints kept per pyramid level, strongest first
+const KEYPOINTS_PER_LEVEL: usize = 250;
+const FAST_THRESHOLD: i16 = 20;
+/// Number of contiguous circle pixels that have to be brighter or darker than the center
+const FAST_ARC: usize = 9;
+/// Radius of the patch used for orientation
+const PATCH_RADIUS: i32 = 15;
+/// Maximum radius of the BRIEF sample points
+const PATTERN_RADIUS: i32 = 12;
+const DESCRIPTOR_BITS: usize = 256;
+/// Maximum hamming distance of a descriptor match
+const MAX_MATCH_DISTANCE: u32 = 64;
+/// Maximum ratio between the best and second best match distance
+const MATCH_RATIO: f32 = 0.8;
+/// Maximum distance of a match from its position predicted by the transformation, relative to the image size
+const INLIER_TOLERANCE: f32 = 0.02;
+/// Number of matches used to generate transformation hypotheses
+const MAX_HYPOTHESIS_MATCHES: usize = 100;
+
+/// Bresenham circle of radius 3 used by FAST
+const CIRCLE: [(i32, i32); 16] = [
+    (0, -3),
+    (1, -3),
+    (2, -2),
+    (3, -1),
+    (3, 0),
+    (3, 1),
+    (2, 2),
+    (1, 3),
+    (0, 3),
+    (-1, 3),
+    (-2, 2),
+    (-3, 1),
+    (-3, 0),
+    (-3, -1),
+    (-2, -2),
+    (-1, -3),
+];
+
+type Descriptor = [u8; DESCRIPTOR_BITS / 8];
+/// Position relative to the longer image edge and descriptor of a corner
+#[derive(Debug, Clone)]
+struct Keypoint {
+    x: f32,
+    y: f32,
+    descriptor: Descriptor,
+}
+
+/// Pair of sample point offsets compared for one descriptor bit
+type BitTest = ((i32, i32), (i32, i32));
+
+/// ORB-style local feature descriptors of an image.
+///
+/// Corners are detected with FAST on a small image pyramid, oriented by their intensity
+/// centroid and described by rotated BRIEF bit tests. They survive cropping, resizing and
+/// moderate filtering, so they can confirm matches between edited copies of an image.
+#[derive(Debug, Clone, Default)]
+pub struct Features {
+    keypoints: Vec<Keypoint>,
+}
+
+impl Features {
+    pub fn extract(image: &DynamicImage) -> Self {
+        let pattern = pattern();
+        let mut level = image.thumbnail(BASE_SIZE, BASE_SIZE).to_luma8();
+        let mut keypoints = Vec::new();
+        let mut scale = 1.0 / level.width().max(level.height()) as f32;
+
+        for _ in 0..PYRAMID_LEVELS {
+            let blurred = image::imageops::blur(&level, 1.0);
+            let mut corners = detect_corners(&level);
+            corners.sort_unstable_by_key(|&(_, _, score)| std::cmp::Reverse(score));
+            keypoints.extend(
+                corners
+                    .into_iter()
+                    .take(KEYPOINTS_PER_LEVEL)
+                    .map(|(x, y, _)| Keypoint {
+                        x: x as f32 * scale,
+                        y: y as f32 * scale,
+                        descriptor: describe(&blurred, x, y, &pattern),
+                    }),
+            );
+
+            let (width, height) = level.dimensions();
+            let (width, height) = (
+                (width as f32 / PYRAMID_SCALE) as u32,
+                (height as f32 / PYRAMID_SCALE) as u32,
+            );
+            if width.min(height) <= 2 * (PATCH_RADIUS as u32 + 1) {
+                break;
+            }
+            level = image::imageops::resize(&level, width, height, FilterType::Triangle);
+            scale *= PYRAMID_SCALE;
+        }
+
+        Self { keypoints }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keypoints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keypoints.is_empty()
+    }
+
+    /// Number of distinctive descriptor matches between both images that agree on a common
+    /// scaling and translation, i.e. that are consistent with one image being a crop of the other
+    pub fn matches(&self, other: &Features) -> usize {
+        if other.keypoints.len() < 2 {
+            return 0;
+        }
+        let matches: Vec<_> = self
+            .keypoints
+            .iter()
+            .filter_map(|keypoint| {
+                let (mut best, mut second) = ((u32::MAX, None), u32::MAX);
+                for candidate in &other.keypoints {
+                    let distance = hamming(&keypoint.descriptor, &candidate.descriptor);
+                    if distance < best.0 {
+                        second = best.0;
+                        best = (distance, Some(candidate));
+                    } else if distance < second {
+                        second = distance;
+                    }
+                }
+                let (distance, candidate) = best;
+                let distinctive = distance <= MAX_MATCH_DISTANCE
+                    && (distance as f32) < MATCH_RATIO * second as f32;
+                Some((keypoint, candidate?)).filter(|_| distinctive)
+            })
+            .collect();
+
+        consistent_matches(&matches)
+    }
+}
+
+/// Size of the largest subset of matches that agree on one scaling and translation
+fn consistent_matches(matches: &[(&Keypoint, &Keypoint)]) -> usize {
+    let hypotheses = &matches[..matches.len().min(MAX_HYPOTHESIS_MATCHES)];
+    let mut best = matches.len().min(1);
+
+    for (i, (p1, q1)) in hypotheses.iter().enumerate() {
+        for (p2, q2) in &hypotheses[i + 1..] {
+            let (dx, dy) = (p2.x - p1.x, p2.y - p1.y);
+            let (qx, qy) = (q2.x - q1.x, q2.y - q1.y);
+            let length = (dx * dx + dy * dy).sqrt();
+            if length < 4.0 * INLIER_TOLERANCE {
+                continue;
+            }
+            let scale = (qx * qx + qy * qy).sqrt() / length;
+            if !(0.25..=4.0).contains(&scale) {
+                continue;
+            }
+            let (tx, ty) = (q1.x - scale * p1.x, q1.y - scale * p1.y);
+
+            let inliers = matches
+                .iter()
+                .filter(|(p, q)| {
+                    let (ex, ey) = (scale * p.x + tx - q.x, scale * p.y + ty - q.y);
+                    (ex * ex + ey * ey).sqrt() <= INLIER_TOLERANCE
+                })
+                .count();
+            best = best.max(inliers);
+        }
+    }
+    best
+}
+
+fn hamming(l: &Descriptor, r: &Descriptor) -> u32 {
+    l.iter().zip(r).map(|(l, r)| (l ^ r).count_ones()).sum()
+}
+
+/// FAST corners as `(x, y, score)` after non-maximum suppression
+fn detect_corners(image: &GrayImage) -> Vec<(u32, u32, u32)> {
+    let (width, height) = image.dimensions();
+    let margin = PATCH_RADIUS as u32 + 1;
+    if width <= 2 * margin || height <= 2 * margin {
+        return Vec::new();
+    }
+
+    let mut scores = vec![0u32; (width * height) as usize];
+    for y in margin..height - margin {
+        for x in margin..width - margin {
+            scores[(y * width + x) as usize] = fast_score(image, x, y);
+        }
+    }
+
+    let mut corners = Vec::new();
+    for y in margin..height - margin {
+        for x in margin..width - margin {
+            let score = scores[(y * width + x) as usize];
+            if score == 0 {
+                continue;
+            }
+            let is_maximum = (y - 1..=y + 1)
+                .flat_map(|ny| (x - 1..=x + 1).map(move |nx| (nx, ny)))
+                .filter(|&(nx, ny)| (nx, ny) != (x, y))
+                .all(|(nx, ny)| scores[(ny * width + nx) as usize] < score);
+            if is_maximum {
+                corners.push((x, y, score));
+            }
+        }
+    }
+    corners
+}
+
+/// Sum of absolute differences to the center if `(x, y)` is a FAST corner, 0 otherwise
+fn fast_score(image: &GrayImage, x: u32, y: u32) -> u32 {
+    let center = image.get_pixel(x, y)[0] as i16;
+    let ring = CIRCLE.map(|(dx, dy)| {
+        image.get_pixel((x as i32 + dx) as u32, (y as i32 + dy) as u32)[0] as i16 - center
+    });
+
+    let longest_arc = |predicate: &dyn Fn(i16) -> bool| {
+        let (mut longest, mut current) = (0, 0);
+        // walk around twice to find arcs wrapping around the start
+        for &diff in ring.iter().chain(&ring) {
+            if predicate(diff) {
+                current += 1;
+                longest = usize::max(longest, current);
+            } else {
+                current = 0;
+            }
+        }
+        longest
+    };
+
+    let brighter = longest_arc(&|diff| diff > FAST_THRESHOLD);
+    let darker = longest_arc(&|diff| diff < -FAST_THRESHOLD);
+    if brighter >= FAST_ARC || darker >= FAST_ARC {
+        ring.iter()
+            .map(|diff| diff.unsigned_abs().saturating_sub(FAST_THRESHOLD as u16) as u32)
+            .sum::<u32>()
+            .max(1)
+    } else {
+        0
+    }
+}
+
+/// Rotated BRIEF descriptor of the keypoint at `(x, y)`
+fn describe(image: &GrayImage, x: u32, y: u32, pattern: &[BitTest]) -> Descriptor {
+    let (x, y) = (x as i32, y as i32);
+    let pixel = |dx: i32, dy: i32| image.get_pixel((x + dx) as u32, (y + dy) as u32)[0];
+
+    // orientation from the intensity centroid of the circular patch
+    let (mut m10, mut m01) = (0i64, 0i64);
+    for dy in -PATCH_RADIUS..=PATCH_RADIUS {
+        for dx in -PATCH_RADIUS..=PATCH_RADIUS {
+            if dx * dx + dy * dy <= PATCH_RADIUS * PATCH_RADIUS {
+                let value = pixel(dx, dy) as i64;
+                m10 += dx as i64 * value;
+                m01 += dy as i64 * value;
+            }
+        }
+    }
+    let angle = (m01 as f32).atan2(m10 as f32);
+    let (sin, cos) = angle.sin_cos();
+    let rotate = |(px, py): (i32, i32)| {
+        let (px, py) = (px as f32, py as f32);
+        (
+            (cos * px - sin * py).round() as i32,
+            (sin * px + cos * py).round() as i32,
+        )
+    };
+
+    let mut descriptor = [0; DESCRIPTOR_BITS / 8];
+    for (bit, &(a, b)) in pattern.iter().enumerate() {
+        let (ax, ay) = rotate(a);
+        let (bx, by) = rotate(b);
+        if pixel(ax, ay) < pixel(bx, by) {
+            descriptor[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+    descriptor
+}
+
+/// Deterministic pseudo random BRIEF sampling pattern with points inside [`PATTERN_RADIUS`]
+fn pattern() -> Vec<BitTest> {
+    // xorshift so the pattern is identical for every run and image
+    let mut state: u32 = 0x9e37_79b9;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    };
+    let mut point = move || loop {
+        let range = (2 * PATTERN_RADIUS + 1) as u32;
+        let x = (next() % range) as i32 - PATTERN_RADIUS;
+        let y = (next() % range) as i32 - PATTERN_RADIUS;
+        if x * x + y * y <= PATTERN_RADIUS * PATTERN_RADIUS {
+            return (x, y);
+        }
+    };
+    (0..DESCRIPTOR_BITS).map(|_| (point(), point())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{imageops::FilterType, GrayImage, Luma};
+
+    use super::*;
+    use crate::grouper::FeatureMatching;
+
+    /// Rectangles of random brightness that give plenty of corners
+    fn textured(seed: u64, width: u32, height: u32) -> DynamicImage {
+        let mut state = seed;
+        let mut random = move |max: u32| {
+            // xorshift, good enough for test patterns
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % u64::from(max)) as u32
+        };
+        let mut image = GrayImage::from_pixel(width, height, Luma([128]));
+        for _ in 0..150 {
+            let (x, y) = (random(width), random(height));
+            let (w, h) = (10 + random(60), 10 + random(60));
+            let brightness = random(256) as u8;
+            for py in y..(y + h).min(height) {
+                for px in x..(x + w).min(width) {
+                    image.put_pixel(px, py, Luma([brightness]));
+                }
+            }
+        }
+        DynamicImage::ImageLuma8(image)
+    }
+
+    #[test]
+    fn match_cropped_and_resized_copy() {
+        let image = textured(1, 480, 360);
+        let edited = image
+            .crop_imm(40, 30, 400, 300)
+            .resize(320, 240, FilterType::Triangle);
+
+        let features = Features::extract(&image);
+        let matches = Features::extract(&edited).matches(&features);
+        assert!(
+            matches >= FeatureMatching::default().min_matches,
+            "only {matches} matches"
+        );
+    }
+
+    #[test]
+    fn reject_unrelated_image() {
+        let features = Features::extract(&textured(1, 480, 360));
+        let unrelated = Features::extract(&textured(2, 480, 360));
+        let matches = unrelated.matches(&features);
+        assert!(
+            matches < FeatureMatching::default().min_matches,
+            "{matches} matches"
+        );
+    }
+
+    #[test]
+    fn tiny_and_flat_images_have_no_features() {
+        // too small for a patch around any corner
+        let tiny = textured(1, 2 * PATCH_RADIUS as u32, 20).to_luma8();
+        assert!(detect_corners(&tiny).is_empty());
+
+        let pixel = Features::extract(&DynamicImage::ImageLuma8(GrayImage::new(1, 1)));
+        let flat = Features::extract(&DynamicImage::ImageLuma8(GrayImage::from_pixel(
+            200,
+            200,
+            Luma([128]),
+        )));
+        assert!(pixel.is_empty());
+        assert!(flat.is_empty());
+        let features = Features::extract(&textured(1, 480, 360));
+        assert_eq!(pixel.matches(&features), 0);
+        assert_eq!(features.matches(&flat), 0);
+    }
+}
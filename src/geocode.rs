@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::BufRead;
+
+use camino::Utf8Path;
+use thiserror::Error;
+
+use crate::location::Location;
+use crate::writer::sanitize_file_name;
+
+/// Kilometers per degree of latitude
+const KM_PER_DEGREE: f64 = 111.2;
+const DEFAULT_MAX_DISTANCE_KM: f64 = 50.0;
+
+/// Named place from a [`Gazetteer`]
+#[derive(Debug, Clone)]
+pub struct Place {
+    pub name: String,
+    pub location: Location,
+}
+
+/// Offline reverse geocoder that maps GPS positions to the nearest known place.
+///
+/// The data is read from a file in the format of the GeoNames dumps (e.g. `cities15000.txt` from
+/// <https://download.geonames.org/export/dump/>) or from a simpler file with the three tab separated
+/// columns name, latitude and longitude. No network access is required, but no place data is bundled either.
+pub struct Gazetteer {
+    places: Vec<Place>,
+    /// Indices into `places` bucketed by their position rounded down to whole degrees
+    grid: HashMap<(i32, i32), Vec<usize>>,
+    max_distance_km: f64,
+}
+
+#[derive(Debug, Error)]
+pub enum GazetteerError {
+    #[error("failed to read gazetteer")]
+    IoError(#[from] std::io::Error),
+    #[error("invalid gazetteer entry in line {0}")]
+    InvalidLine(usize),
+}
+
+impl Gazetteer {
+    pub fn load(path: &Utf8Path) -> Result<Self, GazetteerError> {
+        let file = std::fs::File::open(path)?;
+        Self::from_reader(std::io::BufReader::new(file))
+    }
+
+    pub fn from_reader(reader: impl BufRead) -> Result<Self, GazetteerError> {
+        let mut places = Vec::new();
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let place = parse_line(&line).ok_or(GazetteerError::InvalidLine(number + 1))?;
+            places.push(place);
+        }
+
+        let mut grid: HashMap<_, Vec<_>> = HashMap::new();
+        for (i, place) in places.iter().enumerate() {
+            grid.entry(cell(&place.location)).or_default().push(i);
+        }
+
+        tracing::debug!("Loaded {} places into gazetteer.", places.len());
+        Ok(Self {
+            places,
+            grid,
+            max_distance_km: DEFAULT_MAX_DISTANCE_KM,
+        })
+    }
+
+    /// Only name locations after places that are at most this far away. Defaults to 50km
+    pub fn with_max_distance(mut self, max_distance_km: f64) -> Self {
+        self.max_distance_km = max_distance_km;
+        self
+    }
+
+    /// Finds the place closest to `location` within the maximum distance
+    pub fn nearest(&self, location: &Location) -> Option<&Place> {
+        let (lat_cell, lon_cell) = cell(location);
+        let lat_radius = (self.max_distance_km / KM_PER_DEGREE).ceil() as i32;
+        let lon_radius = {
+            let km_per_degree = KM_PER_DEGREE * location.latitude.to_radians().cos();
+            if km_per_degree <= f64::EPSILON {
+                180
+            } else {
+                ((self.max_distance_km / km_per_degree).ceil() as i32).min(180)
+            }
+        };
+
+        let mut nearest: Option<(f64, &Place)> = None;
+        for lat in lat_cell - lat_radius..=lat_cell + lat_radius {
+            for lon in lon_cell - lon_radius..=lon_cell + lon_radius {
+                // wrap around the antimeridian
+                let lon = (lon + 180).rem_euclid(360) - 180;
+                let Some(indices) = self.grid.get(&(lat, lon)) else {
+                    continue;
+                };
+                for place in indices.iter().map(|&i| &self.places[i]) {
+                    let distance = place.location.distance_km(location);
+                    if distance <= self.max_distance_km
+                        && nearest.is_none_or(|(best, _)| distance < best)
+                    {
+                        nearest = Some((distance, place));
+                    }
+                }
+            }
+        }
+        nearest.map(|(_, place)| place)
+    }
+
+    /// Name of the nearest place usable as part of a file name
+    pub fn place_name(&self, location: &Location) -> Option<String> {
+        self.nearest(location)
+            .map(|place| sanitize_file_name(&place.name))
+    }
+}
+
+impl Debug for Gazetteer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Gazetteer")
+            .field("places", &self.places.len())
+            .field("max_distance_km", &self.max_distance_km)
+            .finish()
+    }
+}
+
+fn cell(location: &Location) -> (i32, i32) {
+    (
+        location.latitude.floor() as i32,
+        location.longitude.floor() as i32,
+    )
+}
+
+fn parse_line(line: &str) -> Option<Place> {
+    let columns: Vec<_> = line.split('\t').collect();
+    let (name, latitude, longitude) = match columns.len() {
+        // GeoNames: geonameid, name, asciiname, alternatenames, latitude, longitude, ...
+        n if n >= 6 => (columns[1], columns[4], columns[5]),
+        3 => (columns[0], columns[1], columns[2]),
+        _ => return None,
+    };
+    let latitude: f64 = latitude.trim().parse().ok()?;
+    let longitude: f64 = longitude.trim().parse().ok()?;
+    let name = name.trim();
+    let valid = !name.is_empty() && latitude.abs() <= 90.0 && longitude.abs() <= 180.0;
+    valid.then(|| Place {
+        name: name.to_owned(),
+        location: Location {
+            latitude,
+            longitude,
+            altitude: None,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(latitude: f64, longitude: f64) -> Location {
+        Location {
+            latitude,
+            longitude,
+            altitude: None,
+        }
+    }
+
+    fn gazetteer() -> Gazetteer {
+        let data = "\
+# name, latitude, longitude
+Lisbon\t38.7167\t-9.1333
+Santarem\t39.2333\t-8.6833
+Suva\t-18.1416\t178.4419
+Apia\t-13.8333\t-171.7667
+";
+        Gazetteer::from_reader(data.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn find_nearest_place() {
+        let gazetteer = gazetteer();
+        let nearest = |latitude, longitude| {
+            gazetteer
+                .nearest(&location(latitude, longitude))
+                .map(|place| place.name.as_str())
+        };
+        assert_eq!(nearest(38.75, -9.1), Some("Lisbon"));
+        assert_eq!(nearest(39.1, -8.7), Some("Santarem"));
+        // the middle of the Atlantic is too far from everything
+        assert_eq!(nearest(38.0, -30.0), None);
+    }
+
+    #[test]
+    fn find_nearest_place_across_antimeridian() {
+        let gazetteer = gazetteer().with_max_distance(300.0);
+        // about 220 km east of Suva, on the other side of the 180th meridian
+        let place = gazetteer.nearest(&location(-18.0, -179.5)).unwrap();
+        assert_eq!(place.name, "Suva");
+        let place = gazetteer.nearest(&location(-13.9, -172.0)).unwrap();
+        assert_eq!(place.name, "Apia");
+    }
+
+    #[test]
+    fn limit_distance() {
+        let gazetteer = gazetteer().with_max_distance(10.0);
+        assert!(gazetteer.nearest(&location(39.1, -8.7)).is_none());
+        assert_eq!(
+            gazetteer.place_name(&location(38.72, -9.13)).as_deref(),
+            Some("Lisbon")
+        );
+    }
+
+    #[test]
+    fn reject_invalid_lines() {
+        let error = Gazetteer::from_reader("Lisbon\t38.7\n".as_bytes()).unwrap_err();
+        assert!(matches!(error, GazetteerError::InvalidLine(1)));
+        let error = Gazetteer::from_reader("\nNowhere\t91\t0\n".as_bytes()).unwrap_err();
+        assert!(matches!(error, GazetteerError::InvalidLine(2)));
+    }
+}
@@ -0,0 +1,528 @@
+use std::collections::HashSet;
+
+use camino::Utf8PathBuf;
+use chrono::{Duration, NaiveDate};
+use itertools::Itertools;
+use rayon::prelude::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use serde::Serialize;
+
+use crate::image::{HashKind, Image};
+use crate::pile::Pile;
+use crate::progress::{NoProgress, Progress};
+
+/// Second stage that groups similar images into piles
+pub struct Grouper<'a> {
+    options: GroupingOptions,
+    progress: &'a dyn Progress,
+}
+
+impl<'a> Grouper<'a> {
+    pub fn new(options: GroupingOptions) -> Self {
+        Self {
+            options,
+            progress: &NoProgress,
+        }
+    }
+
+    pub fn progress(mut self, progress: &'a dyn Progress) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    pub fn options(&self) -> &GroupingOptions {
+        &self.options
+    }
+
+    /// Compares all pairs of images and combines the similar ones into piles.
+    ///
+    /// Every image ends up in exactly one pile, images without similar images form a pile of their own.
+    /// The piles are ordered by their [first image](Pile::first).
+    pub fn group(&self, images: Vec<Image>) -> Grouping {
+        let start = std::time::Instant::now();
+        let total_pairs = images.len() as u64 * images.len().saturating_sub(1) as u64 / 2;
+        self.progress.comparing_pairs(total_pairs);
+        // pairs of image indices, the left index is always the smaller one
+        let mut pairs: Vec<_> = images
+            .par_iter()
+            .enumerate()
+            .flat_map_iter(|(i, l)| {
+                let others = &images[i + 1..];
+                let similar: Vec<_> = others
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, r)| self.options.is_similar(l, r))
+                    .map(|(j, _)| (i, i + 1 + j))
+                    .collect();
+                self.progress.pairs_compared(others.len() as u64);
+                similar
+            })
+            .collect();
+
+        let similar = match self.options.linkage {
+            Linkage::Single => HashSet::new(),
+            Linkage::Complete => {
+                // merge the closest pairs first because they may prevent later merges
+                pairs.sort_by_cached_key(|&(l, r)| {
+                    let (l, r) = (&images[l], &images[r]);
+                    (l.hash_distance(r), l.path(), r.path())
+                });
+                pairs.iter().copied().collect()
+            }
+        };
+        let linked = |pile: &[usize], image: usize| {
+            self.options.linkage == Linkage::Single
+                || pile
+                    .iter()
+                    .all(|&p| similar.contains(&(p.min(image), p.max(image))))
+        };
+
+        let pairs = pairs.into_iter().chain((0..images.len()).map(|i| (i, i)));
+        // piles of image indices, the images are only moved into the piles at the end.
+        // Merged piles are left empty so that the indices of the other piles stay valid
+        let mut piles: Vec<Vec<usize>> = Vec::with_capacity(images.len());
+        // index of the pile each image belongs to
+        let mut pile_of: Vec<Option<usize>> = vec![None; images.len()];
+        for (l, r) in pairs {
+            let (l_image, r_image) = (&images[l], &images[r]);
+            match (pile_of[l], pile_of[r]) {
+                (None, None) => {
+                    let index = piles.len();
+                    piles.push(if l == r { vec![l] } else { vec![l, r] });
+                    pile_of[l] = Some(index);
+                    pile_of[r] = Some(index);
+                    tracing::debug!("Added picture {l_image} to pile {index}");
+                    if l != r {
+                        tracing::debug!("Added picture {r_image} to pile {index}");
+                    }
+                }
+                (Some(pile), None) => {
+                    if linked(&piles[pile], r) {
+                        piles[pile].push(r);
+                        pile_of[r] = Some(pile);
+                        tracing::debug!("Added picture {r_image} to pile {pile} because it also contains picture {l_image}");
+                    }
+                }
+                (None, Some(pile)) => {
+                    if linked(&piles[pile], l) {
+                        piles[pile].push(l);
+                        pile_of[l] = Some(pile);
+                        tracing::debug!("Added picture {l_image} to pile {pile} because it also contains picture {r_image}");
+                    }
+                }
+                (Some(i), Some(j)) => {
+                    if i != j && piles[j].iter().all(|&p| linked(&piles[i], p)) {
+                        // move the smaller pile so that every image is moved at most log(n) times
+                        let (kept, disbanded) = match piles[i].len() >= piles[j].len() {
+                            true => (i, j),
+                            false => (j, i),
+                        };
+                        let disbanding_pile = std::mem::take(&mut piles[disbanded]);
+                        for &image in &disbanding_pile {
+                            pile_of[image] = Some(kept);
+                        }
+                        piles[kept].extend(disbanding_pile);
+                        tracing::debug!("Merged piles {i} and {j} to {kept} because picture {l_image} belonged to pile {i} and picture {r_image} belonged to pile {j}");
+                    }
+                }
+            }
+        }
+
+        let mut images: Vec<_> = images.into_iter().map(Some).collect();
+        let piles: Vec<Pile> = piles
+            .into_iter()
+            .filter(|members| !members.is_empty())
+            .map(|members| {
+                let mut members = members.into_iter().map(|m| {
+                    images[m]
+                        .take()
+                        .expect("every image belongs to exactly one pile")
+                });
+                let mut pile = Pile::new(members.next().expect("piles are never empty"));
+                pile.extend(members);
+                pile
+            })
+            .collect();
+
+        let (mut piles, split_piles) = match self.options.refinement {
+            Some(ref refinement) => refinement.refine(piles),
+            None => (piles, Vec::new()),
+        };
+        // make the pile order independent of the order in which the images were linked
+        piles.sort_by_cached_key(|pile| {
+            let first = pile.first();
+            (first.timestamp, first.path().to_owned())
+        });
+        tracing::trace!("{piles:#?}");
+
+        Grouping {
+            piles,
+            split_piles,
+            attached: Vec::new(),
+            compare_time: start.elapsed(),
+        }
+    }
+
+    /// Groups `images` like [`Grouper::group`] and attaches every resulting pile to the first of the `existing`
+    /// piles it is linked to. The existing piles are never merged or split.
+    pub fn group_into(&self, existing: &[Pile], images: Vec<Image>) -> Grouping {
+        let new_images = images.len();
+        let mut grouping = self.group(images);
+        let start = std::time::Instant::now();
+        let existing_images: usize = existing.iter().map(Pile::len).sum();
+        self.progress
+            .comparing_pairs(new_images as u64 * existing_images as u64);
+
+        let mut piles = Vec::with_capacity(grouping.piles.len());
+        for pile in grouping.piles {
+            let target = existing
+                .par_iter()
+                .position_first(|existing| self.pile_linked(existing, &pile));
+            self.progress
+                .pairs_compared(pile.len() as u64 * existing_images as u64);
+            match target {
+                Some(index) => {
+                    tracing::debug!(
+                        "Attached pile of {} images from {} to existing pile {index}",
+                        pile.len(),
+                        pile.date()
+                    );
+                    grouping.attached.push((index, pile));
+                }
+                None => piles.push(pile),
+            }
+        }
+        grouping.piles = piles;
+        grouping.compare_time += start.elapsed();
+        grouping
+    }
+
+    /// Whether the images of two piles may form one pile under the [`Linkage`]
+    fn pile_linked(&self, l: &Pile, r: &Pile) -> bool {
+        let mut pairs = l.pictures.iter().cartesian_product(&r.pictures);
+        match self.options.linkage {
+            Linkage::Single => pairs.any(|(l, r)| self.options.is_similar(l, r)),
+            Linkage::Complete => pairs.all(|(l, r)| self.options.is_similar(l, r)),
+        }
+    }
+}
+
+/// Result of a [`Grouper`]
+#[derive(Debug, Default)]
+pub struct Grouping {
+    pub piles: Vec<Pile>,
+    /// Piles that were split by the [`Refinement`]
+    pub split_piles: Vec<SplitPile>,
+    /// Piles that belong to the existing pile with the given index, see [`Grouper::group_into`]
+    pub attached: Vec<(usize, Pile)>,
+    /// Time spent comparing and grouping the images
+    pub compare_time: std::time::Duration,
+}
+
+/// Criteria for two images to end up in the same pile
+#[derive(Debug, Clone)]
+pub struct GroupingOptions {
+    /// Maximum time between two images
+    pub max_time_delta: Duration,
+    /// Maximum hamming distance between the perceptual hashes of two images
+    pub max_hash_distance: u32,
+    /// Maximum distance in kilometers between two images. Only applies if both images have a GPS position
+    pub max_distance_km: Option<f64>,
+    /// Confirm pairs above `max_hash_distance` by comparing local features.
+    /// Requires the images to be loaded with [`LoadOptions::features`]
+    pub feature_matching: Option<FeatureMatching>,
+    /// Require several hash algorithms to agree instead of only the blockhash.
+    /// Requires the images to be loaded with [`LoadOptions::multi_hash`]
+    pub voting: Option<HashVoting>,
+    /// How pairs of similar images are combined into piles
+    pub linkage: Linkage,
+    /// Re-cluster piles that grew too large
+    pub refinement: Option<Refinement>,
+    /// Maximum distance between the colour signatures of two images.
+    /// Only applies if both images were loaded with [`LoadOptions::color`]
+    pub max_color_distance: Option<f32>,
+}
+
+impl Default for GroupingOptions {
+    fn default() -> Self {
+        Self {
+            max_time_delta: Duration::minutes(30),
+            max_hash_distance: 10,
+            max_distance_km: Some(1.0),
+            feature_matching: None,
+            voting: None,
+            linkage: Linkage::Single,
+            refinement: None,
+            max_color_distance: None,
+        }
+    }
+}
+
+impl GroupingOptions {
+    pub fn is_similar(&self, l: &Image, r: &Image) -> bool {
+        let time_delta = abs(l.timestamp - r.timestamp);
+        let too_far = match (self.max_distance_km, l.location, r.location) {
+            (Some(max), Some(l), Some(r)) => l.distance_km(&r) > max,
+            _ => false,
+        };
+        let different_colors = match (self.max_color_distance, l.color, r.color) {
+            (Some(max), Some(l), Some(r)) => l.distance(&r) > max,
+            _ => false,
+        };
+        if time_delta >= self.max_time_delta || too_far || different_colors {
+            return false;
+        }
+
+        let hash_distance = l.hash_distance(r);
+        let hashes_agree = match self.voting {
+            Some(ref voting) => voting.votes(l, r, hash_distance < self.max_hash_distance),
+            None => hash_distance < self.max_hash_distance,
+        };
+        if hashes_agree {
+            return true;
+        }
+        match (&self.feature_matching, l.features(), r.features()) {
+            (Some(matching), Some(l_features), Some(r_features))
+                if hash_distance < matching.max_hash_distance =>
+            {
+                let matches = l_features.matches(r_features);
+                tracing::debug!("Found {matches} feature matches between {l} and {r}");
+                matches >= matching.min_matches
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Majority vote of several hash algorithms on whether two images are similar
+#[derive(Debug, Clone)]
+pub struct HashVoting {
+    /// Number of hash algorithms that have to consider two images similar
+    pub min_votes: usize,
+    /// Maximum hamming distance between the DCT hashes of two images
+    pub max_dct_distance: u32,
+    /// Maximum hamming distance between the gradient hashes of two images
+    pub max_gradient_distance: u32,
+}
+
+impl Default for HashVoting {
+    fn default() -> Self {
+        Self {
+            min_votes: 2,
+            max_dct_distance: 10,
+            max_gradient_distance: 10,
+        }
+    }
+}
+
+impl HashVoting {
+    fn votes(&self, l: &Image, r: &Image, blockhash_vote: bool) -> bool {
+        let votes = [
+            (HashKind::Dct, self.max_dct_distance),
+            (HashKind::Gradient, self.max_gradient_distance),
+        ]
+        .into_iter()
+        .filter(|&(kind, max)| l.hash_distance_of(kind, r).is_some_and(|d| d < max))
+        .count()
+            + usize::from(blockhash_vote);
+        votes >= self.min_votes
+    }
+}
+
+/// Rule for combining pairs of similar images into piles
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Linkage {
+    /// Images are in the same pile if they are connected by a chain of similar images
+    #[default]
+    Single,
+    /// Every image of a pile has to be similar to every other image of the pile
+    Complete,
+}
+
+/// Splits oversized piles into smaller coherent ones
+#[derive(Debug, Clone)]
+pub struct Refinement {
+    /// Piles with more images are split
+    pub max_pile_size: usize,
+    /// Maximum average blockhash distance between two clusters to be merged while splitting
+    pub max_distance: u32,
+}
+
+impl Default for Refinement {
+    fn default() -> Self {
+        Self {
+            max_pile_size: 50,
+            max_distance: 8,
+        }
+    }
+}
+
+impl Refinement {
+    fn refine(&self, piles: Vec<Pile>) -> (Vec<Pile>, Vec<SplitPile>) {
+        let mut refined = Vec::with_capacity(piles.len());
+        let mut split_piles = Vec::new();
+        for pile in piles {
+            if pile.len() <= self.max_pile_size {
+                refined.push(pile);
+                continue;
+            }
+
+            let date = pile.date();
+            let size = pile.len();
+            let first_image = pile.first().path().to_owned();
+            let parts = pile.split(self.max_pile_size, self.max_distance);
+
+            let split = SplitPile {
+                date,
+                first_image,
+                size,
+                part_sizes: parts
+                    .iter()
+                    .map(Pile::len)
+                    .sorted_unstable()
+                    .rev()
+                    .collect(),
+            };
+            tracing::debug!("{split}");
+            split_piles.push(split);
+            refined.extend(parts);
+        }
+        (refined, split_piles)
+    }
+}
+
+/// Report of a pile that was split by the [`Refinement`]
+#[derive(Debug, Clone, Serialize)]
+pub struct SplitPile {
+    pub date: NaiveDate,
+    /// Earliest image of the pile
+    pub first_image: Utf8PathBuf,
+    /// Number of images before splitting
+    pub size: usize,
+    /// Sizes of the resulting piles, largest first
+    pub part_sizes: Vec<usize>,
+}
+
+impl std::fmt::Display for SplitPile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Split pile of {} images from {} starting with {} into piles of size {}",
+            self.size,
+            self.date,
+            self.first_image,
+            self.part_sizes.iter().join(", ")
+        )
+    }
+}
+
+/// Second stage matcher for edited copies whose hashes differ too much
+#[derive(Debug, Clone)]
+pub struct FeatureMatching {
+    /// Only compare the features of pairs with a hash distance below this value
+    pub max_hash_distance: u32,
+    /// Minimum number of matching local features to consider two images similar
+    pub min_matches: usize,
+}
+
+impl Default for FeatureMatching {
+    fn default() -> Self {
+        Self {
+            max_hash_distance: 32,
+            min_matches: 20,
+        }
+    }
+}
+
+fn abs(duration: Duration) -> Duration {
+    if duration < Duration::zero() {
+        -duration
+    } else {
+        duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use camino::Utf8Path;
+
+    use super::*;
+    use crate::image::LoadOptions;
+    use crate::testing::{temp_dir, test_image};
+
+    /// Identical images 20 minutes apart, so only neighbours are within the default 30 minutes
+    fn chain(dir: &Utf8Path) -> Vec<Image> {
+        let mut images: Vec<_> = ["a.png", "b.png", "c.png"]
+            .into_iter()
+            .map(|name| test_image(dir, name, 4))
+            .collect();
+        let start = images[0].timestamp;
+        for (i, image) in images.iter_mut().enumerate() {
+            image.timestamp = start + Duration::minutes(20 * i as i64);
+        }
+        images
+    }
+
+    fn pile_sizes(grouping: &Grouping) -> Vec<usize> {
+        grouping.piles.iter().map(Pile::len).sorted().collect()
+    }
+
+    #[test]
+    fn single_linkage_merges_chains() {
+        let dir = temp_dir("grouper-single");
+        let grouper = Grouper::new(GroupingOptions::default());
+        assert_eq!(pile_sizes(&grouper.group(chain(&dir))), [3]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn complete_linkage_splits_chains() {
+        let dir = temp_dir("grouper-complete");
+        let grouper = Grouper::new(GroupingOptions {
+            linkage: Linkage::Complete,
+            ..Default::default()
+        });
+        let grouping = grouper.group(chain(&dir));
+        assert_eq!(pile_sizes(&grouping), [1, 2]);
+        // a and c are too far apart in time, so b ends up with one of them
+        let pair = grouping.piles.iter().find(|pile| pile.len() == 2).unwrap();
+        assert!(pair
+            .pictures
+            .iter()
+            .any(|image| image.path().ends_with("b.png")));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn hash_voting_requires_min_votes() {
+        let dir = temp_dir("grouper-voting");
+        let loading = LoadOptions {
+            multi_hash: true,
+            ..Default::default()
+        };
+        let l = Image::load_with_options(test_image(&dir, "l.png", 4).path(), &loading).unwrap();
+        let r = Image::load_with_options(test_image(&dir, "r.png", 4).path(), &loading).unwrap();
+        let options = |min_votes, max_other_distance| GroupingOptions {
+            voting: Some(HashVoting {
+                min_votes,
+                max_dct_distance: max_other_distance,
+                max_gradient_distance: max_other_distance,
+            }),
+            ..Default::default()
+        };
+
+        // all three hashes of identical images agree
+        assert!(options(3, 10).is_similar(&l, &r));
+        // no distance is below 0, so only the blockhash votes
+        assert!(options(1, 0).is_similar(&l, &r));
+        assert!(!options(2, 0).is_similar(&l, &r));
+
+        // without multi-hash loading, only the blockhash can vote
+        let (l, r) = (
+            Image::load(l.path()).unwrap(),
+            Image::load(r.path()).unwrap(),
+        );
+        assert!(options(1, 10).is_similar(&l, &r));
+        assert!(!options(2, 10).is_similar(&l, &r));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
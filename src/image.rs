@@ -1,72 +1,397 @@
-use std::{fmt::Display, io::Cursor};
+use std::{
+    fmt::Display,
+    io::{Cursor, Read},
+};
 
 use camino::{Utf8Path, Utf8PathBuf};
 use chrono::{NaiveDate, NaiveDateTime};
-use image::io::Reader;
-use image_hasher::{HasherConfig, ImageHash};
+use image::{io::Reader, DynamicImage, GenericImageView, GrayImage, ImageFormat, RgbImage};
+use image_hasher::{HashAlg, Hasher, HasherConfig, ImageHash};
+use serde::Serialize;
 use thiserror::Error;
 
+use crate::color::ColorSignature;
+use crate::features::Features;
+use crate::location::Location;
+use crate::metadata::{self, ExifWriteError};
+use crate::sniff::{FileKind, SNIFF_SIZE};
+
 type HashType = [u8; 16];
 
 pub struct ImageData {
     data: Vec<u8>,
     path: Utf8PathBuf,
+    /// JPEG thumbnail embedded in the EXIF data
+    thumbnail: Option<Vec<u8>>,
     pub timestamp: NaiveDateTime,
+    pub timestamp_source: TimestampSource,
+    /// Camera model from the EXIF data
+    pub camera: Option<String>,
+    /// GPS position from the EXIF data
+    pub location: Option<Location>,
+    /// EXIF orientation tag (1-8) describing how the stored pixels have to be transformed for display
+    pub orientation: u32,
+}
+
+/// Where the timestamp of an image was taken from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampSource {
+    /// One of the EXIF date tags
+    Exif,
+    /// File creation or access time because the image has no EXIF date
+    FileSystem,
+}
+
+impl std::fmt::Display for TimestampSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            TimestampSource::Exif => "exif",
+            TimestampSource::FileSystem => "file system",
+        };
+        f.write_str(name)
+    }
 }
 
 impl ImageData {
     pub fn load(path: &Utf8Path) -> Result<Self, ImageLoadError> {
-        let file = std::fs::read(&path)?;
+        let mut file = Vec::new();
+        let mut reader = std::fs::File::open(path)?;
+        (&mut reader).take(SNIFF_SIZE).read_to_end(&mut file)?;
+        match FileKind::from_header(&file) {
+            FileKind::Image => {}
+            // formats without a signature like TGA can only be recognized by their extension
+            FileKind::Unknown if ImageFormat::from_path(path).is_ok() => {}
+            kind => return Err(ImageLoadError::NotAnImage(kind)),
+        }
+        reader.read_to_end(&mut file)?;
 
-        let timestamp = match parse_time_stamp(&file) {
-            Some(ts) => ts,
+        let exif = read_exif(&file);
+
+        let (timestamp, timestamp_source) = match exif.as_ref().and_then(parse_time_stamp) {
+            Some(ts) => (ts, TimestampSource::Exif),
             None => {
-                let meta = std::fs::metadata(&path)?;
+                let meta = std::fs::metadata(path)?;
                 let fallback: chrono::DateTime<chrono::Local> =
                     meta.created().or_else(|_| meta.accessed())?.into();
-                fallback.naive_local()
+                (fallback.naive_local(), TimestampSource::FileSystem)
             }
         };
 
         Ok(ImageData {
             path: path.into(),
             timestamp,
+            timestamp_source,
+            camera: exif.as_ref().and_then(parse_camera),
+            location: exif.as_ref().and_then(parse_location),
+            orientation: exif.as_ref().and_then(parse_orientation).unwrap_or(1),
+            thumbnail: exif
+                .as_ref()
+                .and_then(metadata::thumbnail)
+                .map(<[u8]>::to_vec),
             data: file,
         })
     }
+
+    /// Upper bound of the memory in bytes needed to decode the image, including the file itself
+    pub fn estimated_memory(&self) -> u64 {
+        let pixels = Reader::new(Cursor::new(&self.data))
+            .with_guessed_format()
+            .ok()
+            .and_then(|reader| reader.into_dimensions().ok())
+            .map_or(0, |(width, height)| u64::from(width) * u64::from(height));
+        // four bytes per pixel for RGBA images
+        self.data.len() as u64 + 4 * pixels
+    }
+
+    /// Writes a copy of the image to `dest` with its timestamp stored in the EXIF `DateTimeOriginal` tag.
+    ///
+    /// Only JPEG, PNG and WebP files are supported. The original file is left untouched.
+    pub fn save_with_exif_timestamp(&self, dest: &Utf8Path) -> Result<(), ExifWriteError> {
+        let file = metadata::with_exif_timestamp(&self.data, self.timestamp)?;
+        std::fs::write(dest, file)?;
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Image {
     path: Utf8PathBuf,
+    /// File size in bytes
+    size: u64,
+    format: Option<ImageFormat>,
     pub timestamp: NaiveDateTime,
-    pub hash: ImageHash<HashType>,
+    pub timestamp_source: TimestampSource,
+    pub location: Option<Location>,
+    /// Perceptual hashes, the blockhash always comes first
+    hashes: Vec<PerceptualHash>,
+    /// Local features if feature extraction is enabled
+    features: Option<Features>,
+    /// Colour moments if colour signatures are enabled
+    pub color: Option<ColorSignature>,
+}
+
+/// Options that control how an image is decoded and hashed
+#[derive(Debug, Clone, Default)]
+pub struct LoadOptions {
+    /// Also hash all rotated and mirrored versions of the image so that rotated copies are recognized
+    pub rotation_invariant: bool,
+    /// Extract local features to confirm matches between cropped or resized copies
+    pub features: bool,
+    /// Also compute the DCT and gradient hashes in addition to the blockhash
+    pub multi_hash: bool,
+    /// Compute a colour signature to tell apart images that only differ in colour
+    pub color: bool,
+    /// Maximum number of bytes used by files and decoded images that are loaded at the same time.
+    /// Only used when loading a whole [`Repository`](crate::Repository). Unlimited if `None`
+    pub memory_budget: Option<u64>,
+    /// Hash the thumbnail embedded in the EXIF data of JPEGs instead of the image itself if it has the same
+    /// aspect ratio. Much faster but wrong if the image was edited without updating its thumbnail.
+    /// Ignored if [`LoadOptions::features`] is enabled because thumbnails are too small for local features
+    pub exif_thumbnails: bool,
+}
+
+/// Perceptual hash algorithms an image can be hashed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashKind {
+    /// Blockhash, always computed
+    Block,
+    /// Mean hash of the discrete cosine transform, similar to pHash
+    Dct,
+    /// Difference hash of neighbouring pixels, also known as dHash
+    Gradient,
+}
+
+impl HashKind {
+    fn hasher(self) -> Hasher<HashType> {
+        let config = HasherConfig::with_bytes_type::<HashType>();
+        match self {
+            HashKind::Block => config.hash_alg(HashAlg::Blockhash),
+            HashKind::Dct => config.hash_alg(HashAlg::Mean).preproc_dct(),
+            HashKind::Gradient => config.hash_alg(HashAlg::Gradient),
+        }
+        .to_hasher()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PerceptualHash {
+    kind: HashKind,
+    hash: ImageHash<HashType>,
+    /// Hashes of the rotated and mirrored versions of the image if rotation invariant hashing is enabled
+    variants: Vec<ImageHash<HashType>>,
+}
+
+impl PerceptualHash {
+    /// Minimum hamming distance to any version of `other`
+    fn dist(&self, other: &PerceptualHash) -> u32 {
+        std::iter::once(&other.hash)
+            .chain(&other.variants)
+            .map(|hash| self.hash.dist(hash))
+            .min()
+            .unwrap_or(u32::MAX)
+    }
 }
 
+/// Maximum edge length the image is scaled down to before hashing its rotated and mirrored versions
+const VARIANT_SIZE: u32 = 512;
+/// Minimum length of the longer edge JPEGs are decoded at with DCT scaling. Large enough for all hashes and local features
+const FAST_DECODE_SIZE: u32 = 512;
+/// Minimum length of the longer edge of an EXIF thumbnail to be hashed instead of the image
+const MIN_THUMBNAIL_SIZE: u32 = 120;
+/// Maximum relative difference between the aspect ratios of an EXIF thumbnail and its image
+const THUMBNAIL_RATIO_TOLERANCE: f32 = 0.02;
+
 impl Image {
     pub fn path(&self) -> &Utf8Path {
         &self.path
     }
 
     pub fn load(path: &Utf8Path) -> Result<Self, ImageLoadError> {
-        let image_data = ImageData::load(path)?;
+        Self::load_with_options(path, &LoadOptions::default())
+    }
+
+    pub fn load_with_options(
+        path: &Utf8Path,
+        options: &LoadOptions,
+    ) -> Result<Self, ImageLoadError> {
+        Self::from_data(ImageData::load(path)?, options)
+    }
 
-        let base_image = {
-            let file_cursor = Cursor::new(&image_data.data);
-            Reader::new(file_cursor).with_guessed_format()?.decode()?
+    /// Decodes and hashes an image whose file was already read with [`ImageData::load`]
+    pub fn from_data(image_data: ImageData, options: &LoadOptions) -> Result<Self, ImageLoadError> {
+        let base_image = match decode_jpeg_fast(&image_data, options) {
+            Some(image) => image,
+            None => {
+                let file_cursor = Cursor::new(&image_data.data);
+                Reader::new(file_cursor).with_guessed_format()?.decode()?
+            }
         };
+        let base_image = apply_orientation(base_image, image_data.orientation);
 
-        let hasher = HasherConfig::with_bytes_type::<HashType>()
-            .hash_alg(image_hasher::HashAlg::Blockhash)
-            .to_hasher();
-        let hash = hasher.hash_image(&base_image);
+        let kinds: &[_] = if options.multi_hash {
+            &[HashKind::Block, HashKind::Dct, HashKind::Gradient]
+        } else {
+            &[HashKind::Block]
+        };
+
+        let hashes = if options.rotation_invariant {
+            // all variants must be hashed at the same resolution to be comparable
+            let small = base_image.thumbnail(VARIANT_SIZE, VARIANT_SIZE);
+            let variants = dihedral_variants(&small);
+            kinds
+                .iter()
+                .map(|&kind| {
+                    let hasher = kind.hasher();
+                    PerceptualHash {
+                        kind,
+                        hash: hasher.hash_image(&small),
+                        variants: variants.iter().map(|v| hasher.hash_image(v)).collect(),
+                    }
+                })
+                .collect()
+        } else {
+            kinds
+                .iter()
+                .map(|&kind| PerceptualHash {
+                    kind,
+                    hash: kind.hasher().hash_image(&base_image),
+                    variants: Vec::new(),
+                })
+                .collect()
+        };
+
+        let features = options.features.then(|| Features::extract(&base_image));
 
         Ok(Image {
+            size: image_data.data.len() as u64,
+            format: image::guess_format(&image_data.data).ok(),
             path: image_data.path,
             timestamp: image_data.timestamp,
-            hash,
+            timestamp_source: image_data.timestamp_source,
+            location: image_data.location,
+            hashes,
+            features,
+            color: options
+                .color
+                .then(|| ColorSignature::from_image(&base_image)),
         })
     }
+
+    /// Blockhash of the image
+    pub fn hash(&self) -> &ImageHash<HashType> {
+        // the blockhash is always computed first
+        &self.hashes[0].hash
+    }
+
+    /// Hamming distance between the blockhashes of both images.
+    ///
+    /// If the images were loaded with [`LoadOptions::rotation_invariant`], this is the minimum
+    /// distance across all rotated and mirrored versions of `other`.
+    pub fn hash_distance(&self, other: &Image) -> u32 {
+        self.hashes[0].dist(&other.hashes[0])
+    }
+
+    /// Hamming distance between the hashes of the given kind or `None` if
+    /// one of the images was loaded without [`LoadOptions::multi_hash`]
+    pub fn hash_distance_of(&self, kind: HashKind, other: &Image) -> Option<u32> {
+        fn find(image: &Image, kind: HashKind) -> Option<&PerceptualHash> {
+            image.hashes.iter().find(|h| h.kind == kind)
+        }
+        Some(find(self, kind)?.dist(find(other, kind)?))
+    }
+
+    pub fn features(&self) -> Option<&Features> {
+        self.features.as_ref()
+    }
+
+    /// File size in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Format detected from the file content, `None` for formats without a signature like TGA
+    pub fn format(&self) -> Option<ImageFormat> {
+        self.format
+    }
+}
+
+/// Decodes a JPEG at a reduced resolution, either from its EXIF thumbnail or by scaling down
+/// while decoding the DCT blocks. Returns `None` if the image has to be decoded normally.
+fn decode_jpeg_fast(image_data: &ImageData, options: &LoadOptions) -> Option<DynamicImage> {
+    use jpeg_decoder::{Decoder, PixelFormat};
+
+    if image::guess_format(&image_data.data).ok()? != ImageFormat::Jpeg {
+        return None;
+    }
+    let mut decoder = Decoder::new(Cursor::new(&image_data.data));
+    decoder.read_info().ok()?;
+    let info = decoder.info()?;
+    let (width, height) = (u32::from(info.width), u32::from(info.height));
+
+    let thumbnail = image_data
+        .thumbnail
+        .as_deref()
+        .filter(|_| options.exif_thumbnails && !options.features)
+        .and_then(|thumbnail| {
+            image::load_from_memory_with_format(thumbnail, ImageFormat::Jpeg).ok()
+        })
+        .filter(|thumbnail| {
+            // letterboxed or cropped thumbnails differ from the image
+            let ratio = |w: u32, h: u32| w as f32 / h as f32;
+            let (thumb_width, thumb_height) = thumbnail.dimensions();
+            thumb_width.max(thumb_height) >= MIN_THUMBNAIL_SIZE
+                && (ratio(thumb_width, thumb_height) / ratio(width, height) - 1.0).abs()
+                    <= THUMBNAIL_RATIO_TOLERANCE
+        });
+    if thumbnail.is_some() {
+        tracing::trace!("Using EXIF thumbnail of {}", image_data.path);
+        return thumbnail;
+    }
+
+    let longest = width.max(height);
+    // leave the colour conversion of the remaining formats to the image crate, before decoding anything
+    let supported = matches!(info.pixel_format, PixelFormat::RGB24 | PixelFormat::L8);
+    if !supported || longest < 2 * FAST_DECODE_SIZE {
+        return None;
+    }
+    let scaled = |edge: u32| u16::try_from((edge * FAST_DECODE_SIZE).div_ceil(longest)).ok();
+    let (width, height) = decoder.scale(scaled(width)?, scaled(height)?).ok()?;
+    let pixels = decoder.decode().ok()?;
+    let (width, height) = (u32::from(width), u32::from(height));
+    match info.pixel_format {
+        PixelFormat::RGB24 => {
+            RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8)
+        }
+        PixelFormat::L8 => GrayImage::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+        PixelFormat::L16 | PixelFormat::CMYK32 => {
+            unreachable!("unsupported formats are not decoded")
+        }
+    }
+}
+
+/// Transforms the stored pixels into the orientation the image is supposed to be displayed in
+fn apply_orientation(image: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+/// All rotated and mirrored versions of the image except the image itself
+fn dihedral_variants(image: &DynamicImage) -> Vec<DynamicImage> {
+    let rotations = [image.rotate90(), image.rotate180(), image.rotate270()];
+    let mirrored = std::iter::once(image)
+        .chain(&rotations)
+        .map(DynamicImage::fliph)
+        .collect::<Vec<_>>();
+    rotations.into_iter().chain(mirrored).collect()
 }
 
 impl std::hash::Hash for Image {
@@ -102,24 +427,102 @@ pub enum ImageLoadError {
     InvalidExif(#[from] exif::Error),
     #[error("invalid image")]
     InvalidImage(#[from] image::error::ImageError),
+    #[error("file type {0} is not an image")]
+    NotAnImage(FileKind),
 }
 
-fn parse_time_stamp(file: &[u8]) -> Option<NaiveDateTime> {
-    use exif::{In, Reader, Tag};
+impl ImageLoadError {
+    /// Short name of the error variant for reports
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ImageLoadError::IoError(_) => "io",
+            ImageLoadError::InvalidExif(_) => "invalid_exif",
+            ImageLoadError::InvalidImage(_) => "invalid_image",
+            ImageLoadError::NotAnImage(_) => "not_an_image",
+        }
+    }
+}
+
+fn read_exif(file: &[u8]) -> Option<exif::Exif> {
     let mut file_cursor = Cursor::new(file);
-    let exif = Reader::new().read_from_container(&mut file_cursor).ok()?;
+    exif::Reader::new()
+        .read_from_container(&mut file_cursor)
+        .ok()
+}
 
-    let try_extract_datetime = |tag: Tag, ifd: In| -> Option<NaiveDateTime> {
-        fn value_to_string(value: &exif::Value) -> Option<String> {
-            match value {
-                exif::Value::Ascii(ref a) => Some(
-                    a.iter()
-                        .flat_map(|c| c.iter().copied().map(char::from))
-                        .collect(),
-                ),
-                _ => None,
+fn value_to_string(value: &exif::Value) -> Option<String> {
+    match value {
+        exif::Value::Ascii(ref a) => Some(
+            a.iter()
+                .flat_map(|c| c.iter().copied().map(char::from))
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+fn parse_orientation(exif: &exif::Exif) -> Option<u32> {
+    use exif::{In, Tag};
+    let orientation = exif
+        .get_field(Tag::Orientation, In::PRIMARY)?
+        .value
+        .get_uint(0)?;
+    (1..=8).contains(&orientation).then_some(orientation)
+}
+
+fn parse_camera(exif: &exif::Exif) -> Option<String> {
+    use exif::{In, Tag};
+    let model = value_to_string(&exif.get_field(Tag::Model, In::PRIMARY)?.value)?;
+    let model = model.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    (!model.is_empty()).then(|| model.to_owned())
+}
+
+fn parse_location(exif: &exif::Exif) -> Option<Location> {
+    use exif::{In, Tag, Value};
+
+    let coordinate = |tag: Tag, ref_tag: Tag, negative_ref: &str| -> Option<f64> {
+        let degrees = match exif.get_field(tag, In::PRIMARY)?.value {
+            Value::Rational(ref dms) if dms.len() >= 3 => {
+                dms[0].to_f64() + dms[1].to_f64() / 60.0 + dms[2].to_f64() / 3600.0
             }
+            _ => return None,
+        };
+        let reference = value_to_string(&exif.get_field(ref_tag, In::PRIMARY)?.value)?;
+        let negative = reference.trim().eq_ignore_ascii_case(negative_ref);
+        degrees
+            .is_finite()
+            .then(|| if negative { -degrees } else { degrees })
+    };
+
+    let latitude = coordinate(Tag::GPSLatitude, Tag::GPSLatitudeRef, "S")?;
+    let longitude = coordinate(Tag::GPSLongitude, Tag::GPSLongitudeRef, "W")?;
+    let altitude = match exif
+        .get_field(Tag::GPSAltitude, In::PRIMARY)
+        .map(|f| &f.value)
+    {
+        Some(Value::Rational(alt)) if !alt.is_empty() => {
+            let below_sea_level = exif
+                .get_field(Tag::GPSAltitudeRef, In::PRIMARY)
+                .and_then(|f| f.value.get_uint(0))
+                == Some(1);
+            let alt = alt[0].to_f64();
+            alt.is_finite()
+                .then(|| if below_sea_level { -alt } else { alt })
         }
+        _ => None,
+    };
+
+    Some(Location {
+        latitude,
+        longitude,
+        altitude,
+    })
+}
+
+fn parse_time_stamp(exif: &exif::Exif) -> Option<NaiveDateTime> {
+    use exif::{In, Tag};
+
+    let try_extract_datetime = |tag: Tag, ifd: In| -> Option<NaiveDateTime> {
         let f = exif.get_field(tag, ifd)?;
         let dt: String = value_to_string(&f.value)?;
         let dt = exif::DateTime::from_ascii(dt.as_bytes()).ok()?;
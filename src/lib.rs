@@ -1,8 +1,46 @@
+mod budget;
+mod color;
+mod diff;
+mod event;
+mod existing;
+mod features;
+mod geocode;
+mod grouper;
 mod image;
+mod location;
+mod manifest;
+mod metadata;
 mod pile;
+mod progress;
 mod repository;
+mod review;
+mod scanner;
+mod sniff;
+mod stats;
+#[cfg(test)]
+mod testing;
+mod writer;
 
-pub use crate::image::ImageData;
+pub use crate::image::{HashKind, Image, ImageData, ImageLoadError, LoadOptions, TimestampSource};
+pub use color::ColorSignature;
+pub use diff::{diff, Diff};
+pub use event::{cluster_events, Event};
+pub use existing::{ExistingPiles, ExistingPilesError};
+pub use features::Features;
+pub use geocode::{Gazetteer, GazetteerError, Place};
+pub use grouper::{
+    FeatureMatching, Grouper, Grouping, GroupingOptions, HashVoting, Linkage, Refinement, SplitPile,
+};
+pub use location::Location;
+pub use manifest::{Manifest, ManifestEntry, ManifestError, MANIFEST_FILE};
+pub use metadata::ExifWriteError;
+pub use pile::Pile;
+pub use progress::{NoProgress, Progress};
 pub use repository::Repository;
+pub use review::{PileReview, Review, ReviewError};
+pub use scanner::{LoadFailure, Scan, ScanOptions, Scanner};
+pub use sniff::FileKind;
+pub use stats::{PileSummary, Stats, Timings};
+pub use writer::{sanitize_file_name, OutputOptions, PileWriter, WriteError, UNREADABLE_PILE};
 
 pub const DATETIME_FORMATTER: &str = "%FT%H-%M-%S";
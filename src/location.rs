@@ -0,0 +1,107 @@
+use std::fmt::Display;
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// GPS position where an image was taken
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    /// Degrees north of the equator, negative for the southern hemisphere
+    pub latitude: f64,
+    /// Degrees east of the prime meridian, negative for the western hemisphere
+    pub longitude: f64,
+    /// Meters above sea level
+    pub altitude: Option<f64>,
+}
+
+impl Location {
+    /// Great-circle distance to `other` in kilometers, ignoring the altitude
+    pub fn distance_km(&self, other: &Location) -> f64 {
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let d_lat = lat2 - lat1;
+        let d_lon = (other.longitude - self.longitude).to_radians();
+
+        let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+    }
+
+    /// Average position of all given locations or `None` if there are none.
+    ///
+    /// The positions are averaged as vectors from the center of the earth, so locations on both sides
+    /// of the 180th meridian are averaged correctly.
+    pub fn centroid<'a>(locations: impl IntoIterator<Item = &'a Location>) -> Option<Location> {
+        let mut count = 0;
+        let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+        let (mut altitude, mut altitude_count) = (0.0, 0);
+        for location in locations {
+            count += 1;
+            let (latitude, longitude) = (
+                location.latitude.to_radians(),
+                location.longitude.to_radians(),
+            );
+            x += latitude.cos() * longitude.cos();
+            y += latitude.cos() * longitude.sin();
+            z += latitude.sin();
+            if let Some(alt) = location.altitude {
+                altitude += alt;
+                altitude_count += 1;
+            }
+        }
+        (count > 0).then(|| Location {
+            latitude: z.atan2(x.hypot(y)).to_degrees(),
+            longitude: y.atan2(x).to_degrees(),
+            altitude: (altitude_count > 0).then(|| altitude / altitude_count as f64),
+        })
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:.6}, {:.6}", self.latitude, self.longitude)?;
+        if let Some(altitude) = self.altitude {
+            write!(f, ", {altitude:.1}m")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(latitude: f64, longitude: f64) -> Location {
+        Location {
+            latitude,
+            longitude,
+            altitude: None,
+        }
+    }
+
+    #[test]
+    fn distance() {
+        let lisbon = location(38.7167, -9.1333);
+        let porto = location(41.15, -8.61);
+        assert!((lisbon.distance_km(&porto) - 274.0).abs() < 2.0);
+        assert_eq!(lisbon.distance_km(&lisbon), 0.0);
+        // a degree of latitude is about 111 km everywhere
+        assert!((location(0.0, 0.0).distance_km(&location(1.0, 0.0)) - 111.2).abs() < 0.1);
+        // the short way across the 180th meridian
+        let distance = location(0.0, 179.5).distance_km(&location(0.0, -179.5));
+        assert!((distance - 111.2).abs() < 0.1);
+    }
+
+    #[test]
+    fn centroid() {
+        assert!(Location::centroid(&[]).is_none());
+
+        let mut with_altitude = location(10.0, 20.0);
+        with_altitude.altitude = Some(100.0);
+        let center = Location::centroid(&[with_altitude, location(-10.0, 20.0)]).unwrap();
+        assert!(center.latitude.abs() < 1e-9);
+        assert!((center.longitude - 20.0).abs() < 1e-9);
+        assert_eq!(center.altitude, Some(100.0));
+
+        // averaging the degrees would end up on the other side of the earth at 0°
+        let center = Location::centroid(&[location(0.0, 179.0), location(0.0, -179.0)]).unwrap();
+        assert!((center.longitude.abs() - 180.0).abs() < 1e-9);
+    }
+}
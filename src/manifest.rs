@@ -0,0 +1,188 @@
+use std::fs;
+use std::io::{BufRead, BufWriter, Write};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use chrono::NaiveDateTime;
+use thiserror::Error;
+
+use crate::image::Image;
+use crate::location::Location;
+
+/// Name of the file in the destination that lists all sorted images
+pub const MANIFEST_FILE: &str = "manifest.tsv";
+
+const TIMESTAMP_FORMAT: &str = "%F %T";
+
+/// List of all images linked into a destination by the [`PileWriter`](crate::PileWriter)
+#[derive(Debug, Clone, Default)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// Image linked into a pile folder
+#[derive(Debug, Clone)]
+pub struct ManifestEntry {
+    /// Path of the link relative to the destination
+    pub file: Utf8PathBuf,
+    /// Path of the original image
+    pub source: Utf8PathBuf,
+    pub timestamp: NaiveDateTime,
+    pub location: Option<Location>,
+}
+
+#[derive(Debug, Error)]
+pub enum ManifestError {
+    #[error("failed to read manifest")]
+    IoError(#[from] std::io::Error),
+    #[error("invalid manifest entry in line {0}")]
+    InvalidLine(usize),
+}
+
+impl Manifest {
+    pub fn load(path: &Utf8Path) -> Result<Self, ManifestError> {
+        let file = fs::File::open(path)?;
+        Self::from_reader(std::io::BufReader::new(file))
+    }
+
+    pub fn from_reader(reader: impl BufRead) -> Result<Self, ManifestError> {
+        let mut entries = Vec::new();
+        // the first line is the header
+        for (number, line) in reader.lines().enumerate().skip(1) {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry = parse_line(&line).ok_or(ManifestError::InvalidLine(number + 1))?;
+            entries.push(entry);
+        }
+        Ok(Self { entries })
+    }
+}
+
+fn parse_line(line: &str) -> Option<ManifestEntry> {
+    let columns: Vec<_> = line.split('\t').collect();
+    let [file, source, timestamp, latitude, longitude, altitude] = columns[..] else {
+        return None;
+    };
+    let location = match (latitude, longitude) {
+        ("", "") => None,
+        (latitude, longitude) => Some(Location {
+            latitude: latitude.parse().ok()?,
+            longitude: longitude.parse().ok()?,
+            altitude: match altitude {
+                "" => None,
+                altitude => Some(altitude.parse().ok()?),
+            },
+        }),
+    };
+    Some(ManifestEntry {
+        file: file.into(),
+        source: source.into(),
+        timestamp: NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok()?,
+        location,
+    })
+}
+
+/// Writes a tab separated list of all sorted images with their pile, origin, timestamp and location.
+/// With `append` the images are added to the end of the manifest if there is one already
+pub(crate) fn write_manifest(
+    path: &Utf8Path,
+    dest: &Utf8Path,
+    manifest: &[(Utf8PathBuf, &Image)],
+    append: bool,
+) -> std::io::Result<()> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)?;
+    let new = file.metadata()?.len() == 0;
+    let mut file = BufWriter::new(file);
+
+    if new {
+        writeln!(
+            file,
+            "file\tsource\ttimestamp\tlatitude\tlongitude\taltitude"
+        )?;
+    }
+    for (link, image) in manifest {
+        let link = link.strip_prefix(dest).unwrap_or(link);
+        let timestamp = image.timestamp.format(TIMESTAMP_FORMAT);
+        write!(file, "{link}\t{}\t{timestamp}", image.path())?;
+        match image.location {
+            Some(location) => {
+                write!(file, "\t{}\t{}\t", location.latitude, location.longitude)?;
+                if let Some(altitude) = location.altitude {
+                    write!(file, "{altitude}")?;
+                }
+                writeln!(file)?;
+            }
+            None => writeln!(file, "\t\t\t")?,
+        }
+    }
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Timelike;
+
+    use super::*;
+    use crate::testing::{temp_dir, test_image};
+
+    #[test]
+    fn write_and_load_manifest() {
+        let dir = temp_dir("manifest");
+        let (first, second) = (test_image(&dir, "a.png", 2), test_image(&dir, "b.png", 4));
+        let path = dir.join(MANIFEST_FILE);
+
+        write_manifest(&path, &dir, &[(dir.join("pile/a.png"), &first)], false).unwrap();
+        write_manifest(&path, &dir, &[(dir.join("pile/b.png"), &second)], true).unwrap();
+        let manifest = Manifest::load(&path).unwrap();
+
+        assert_eq!(manifest.entries.len(), 2);
+        for (entry, image) in manifest.entries.iter().zip([&first, &second]) {
+            assert_eq!(entry.file.parent(), Some(Utf8Path::new("pile")));
+            assert_eq!(entry.source, image.path());
+            assert_eq!(entry.timestamp, image.timestamp.with_nanosecond(0).unwrap());
+            assert_eq!(entry.location, None);
+        }
+
+        // without appending the manifest starts over
+        write_manifest(&path, &dir, &[(dir.join("pile/b.png"), &second)], false).unwrap();
+        let manifest = Manifest::load(&path).unwrap();
+        assert_eq!(manifest.entries.len(), 1);
+        assert_eq!(manifest.entries[0].file, "pile/b.png");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn read_locations() {
+        let manifest = "file\tsource\ttimestamp\tlatitude\tlongitude\taltitude\n\
+            a.jpg\t/src/a.jpg\t2022-08-14 10:00:00\t48.5\t-9.25\t\n\
+            b.jpg\t/src/b.jpg\t2022-08-14 10:01:00\t48.5\t-9.25\t120.5\n\
+            c.jpg\t/src/c.jpg\t2022-08-14 10:02:00\t\t\t\n";
+        let manifest = Manifest::from_reader(manifest.as_bytes()).unwrap();
+
+        let locations: Vec<_> = manifest.entries.iter().map(|e| e.location).collect();
+        let location = |altitude| Location {
+            latitude: 48.5,
+            longitude: -9.25,
+            altitude,
+        };
+        assert_eq!(
+            locations,
+            [Some(location(None)), Some(location(Some(120.5))), None]
+        );
+    }
+
+    #[test]
+    fn reject_invalid_lines() {
+        let manifest = "file\tsource\ttimestamp\tlatitude\tlongitude\taltitude\n\
+            a.jpg\t/src/a.jpg\t2022-08-14 10:00:00\t\t\t\n\
+            b.jpg\t/src/b.jpg\tyesterday\t\t\t\n";
+        let error = Manifest::from_reader(manifest.as_bytes()).unwrap_err();
+        assert!(matches!(error, ManifestError::InvalidLine(3)));
+    }
+}
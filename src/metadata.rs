@@ -0,0 +1,166 @@
+use std::io::Cursor;
+
+use chrono::{NaiveDateTime, TimeZone};
+use exif::{experimental::Writer, Field, In, Tag, Value};
+use img_parts::{Bytes, DynImage, ImageEXIF};
+use thiserror::Error;
+
+const EXIF_DATETIME_FORMATTER: &str = "%Y:%m:%d %H:%M:%S";
+
+#[derive(Debug, Error)]
+pub enum ExifWriteError {
+    #[error("failed to write image")]
+    IoError(#[from] std::io::Error),
+    #[error("failed to encode exif data")]
+    InvalidExif(#[from] exif::Error),
+    #[error("failed to parse image container")]
+    InvalidContainer(#[from] img_parts::Error),
+    #[error("writing exif data is only supported for JPEG, PNG and WebP")]
+    UnsupportedFormat,
+}
+
+/// Returns a copy of `file` whose EXIF data contains `timestamp` as `DateTimeOriginal`.
+///
+/// All other existing EXIF fields including the thumbnail are preserved. The offset
+/// is the one of the local time zone at the given time.
+pub(crate) fn with_exif_timestamp(
+    file: &[u8],
+    timestamp: NaiveDateTime,
+) -> Result<Vec<u8>, ExifWriteError> {
+    let mut image = DynImage::from_bytes(Bytes::copy_from_slice(file))?
+        .ok_or(ExifWriteError::UnsupportedFormat)?;
+
+    let existing = image
+        .exif()
+        .and_then(|raw| exif::Reader::new().read_raw(raw.to_vec()).ok());
+
+    let date_time = ascii_field(
+        Tag::DateTimeOriginal,
+        timestamp.format(EXIF_DATETIME_FORMATTER).to_string(),
+    );
+    let offset = chrono::Local
+        .offset_from_local_datetime(&timestamp)
+        .earliest()
+        .map(|offset| ascii_field(Tag::OffsetTimeOriginal, offset.to_string()));
+
+    let mut writer = Writer::new();
+    writer.push_field(&date_time);
+    if let Some(ref offset) = offset {
+        writer.push_field(offset);
+    }
+
+    let mut little_endian = false;
+    if let Some(ref existing) = existing {
+        little_endian = existing.little_endian();
+        existing
+            .fields()
+            .filter(|f| f.tag != Tag::DateTimeOriginal && f.tag != Tag::OffsetTimeOriginal)
+            .filter(|f| !matches!(f.value, Value::Unknown(..)))
+            .for_each(|f| writer.push_field(f));
+        if let Some(thumbnail) = thumbnail(existing) {
+            writer.set_jpeg(thumbnail, In::THUMBNAIL);
+        }
+    }
+
+    let mut buf = Cursor::new(Vec::new());
+    writer.write(&mut buf, little_endian)?;
+    image.set_exif(Some(buf.into_inner().into()));
+
+    let mut out = Vec::with_capacity(image.len());
+    image.encoder().write_to(&mut out)?;
+    Ok(out)
+}
+
+fn ascii_field(tag: Tag, value: String) -> Field {
+    Field {
+        tag,
+        ifd_num: In::PRIMARY,
+        value: Value::Ascii(vec![value.into_bytes()]),
+    }
+}
+
+/// Embedded JPEG thumbnail of the EXIF data
+pub(crate) fn thumbnail(exif: &exif::Exif) -> Option<&[u8]> {
+    let offset = exif
+        .get_field(Tag::JPEGInterchangeFormat, In::THUMBNAIL)?
+        .value
+        .get_uint(0)? as usize;
+    let len = exif
+        .get_field(Tag::JPEGInterchangeFormatLength, In::THUMBNAIL)?
+        .value
+        .get_uint(0)? as usize;
+    exif.buf().get(offset..offset.checked_add(len)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use image::{ImageOutputFormat, RgbImage};
+
+    use super::*;
+    use crate::image::{ImageData, TimestampSource};
+    use crate::testing::temp_dir;
+
+    fn encode(format: ImageOutputFormat) -> Vec<u8> {
+        let mut file = Cursor::new(Vec::new());
+        RgbImage::new(16, 16).write_to(&mut file, format).unwrap();
+        file.into_inner()
+    }
+
+    fn date_time_original(file: &[u8]) -> Vec<String> {
+        let image = DynImage::from_bytes(Bytes::copy_from_slice(file))
+            .unwrap()
+            .unwrap();
+        let exif = exif::Reader::new()
+            .read_raw(image.exif().unwrap().to_vec())
+            .unwrap();
+        exif.fields()
+            .filter(|field| field.tag == Tag::DateTimeOriginal)
+            .map(|field| field.display_value().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn write_date_time_original() {
+        let timestamp = NaiveDate::from_ymd_opt(2022, 7, 14)
+            .unwrap()
+            .and_hms_opt(10, 30, 15)
+            .unwrap();
+        for format in [ImageOutputFormat::Jpeg(90), ImageOutputFormat::Png] {
+            let file = with_exif_timestamp(&encode(format), timestamp).unwrap();
+            assert_eq!(date_time_original(&file), ["2022-07-14 10:30:15"]);
+
+            // an existing timestamp is replaced instead of duplicated
+            let later = timestamp + chrono::Duration::days(1);
+            let file = with_exif_timestamp(&file, later).unwrap();
+            assert_eq!(date_time_original(&file), ["2022-07-15 10:30:15"]);
+        }
+    }
+
+    #[test]
+    fn reject_unsupported_formats() {
+        let bmp = encode(ImageOutputFormat::Bmp);
+        let error = with_exif_timestamp(&bmp, NaiveDateTime::default()).unwrap_err();
+        assert!(matches!(error, ExifWriteError::UnsupportedFormat));
+    }
+
+    #[test]
+    fn load_saved_timestamp() {
+        let dir = temp_dir("metadata-exif");
+        let (original, copy) = (dir.join("original.jpg"), dir.join("copy.jpg"));
+        std::fs::write(&original, encode(ImageOutputFormat::Jpeg(90))).unwrap();
+        let mut data = ImageData::load(&original).unwrap();
+        assert_eq!(data.timestamp_source, TimestampSource::FileSystem);
+        let timestamp = NaiveDate::from_ymd_opt(2001, 2, 3)
+            .unwrap()
+            .and_hms_opt(4, 5, 6)
+            .unwrap();
+        data.timestamp = timestamp;
+
+        data.save_with_exif_timestamp(&copy).unwrap();
+        let copy = ImageData::load(&copy).unwrap();
+        assert_eq!(copy.timestamp, timestamp);
+        assert_eq!(copy.timestamp_source, TimestampSource::Exif);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
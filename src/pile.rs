@@ -1,8 +1,11 @@
-use std::collections::HashSet;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashSet};
 
-use chrono::NaiveDate;
+use chrono::{NaiveDate, NaiveDateTime};
+use itertools::{Itertools, MinMaxResult};
 
 use crate::image::Image;
+use crate::location::Location;
 
 #[derive(Debug, Clone)]
 pub struct Pile {
@@ -20,6 +23,56 @@ impl Pile {
         self.date
     }
 
+    /// Timestamps of the earliest and latest image in the pile
+    pub fn time_range(&self) -> (NaiveDateTime, NaiveDateTime) {
+        match self.pictures.iter().map(|image| image.timestamp).minmax() {
+            MinMaxResult::OneElement(ts) => (ts, ts),
+            MinMaxResult::MinMax(min, max) => (min, max),
+            // any pile has at least one element
+            MinMaxResult::NoElements => unreachable!("piles may never be empty"),
+        }
+    }
+
+    /// Earliest image of the pile, images taken at the same time are ordered by their path
+    pub fn first(&self) -> &Image {
+        self.pictures
+            .iter()
+            .min_by_key(|image| (image.timestamp, image.path()))
+            .expect("piles may never be empty")
+    }
+
+    /// Images of the pile ordered like [`Pile::first`]
+    pub fn sorted(&self) -> Vec<&Image> {
+        self.pictures
+            .iter()
+            .sorted_unstable_by_key(|image| (image.timestamp, image.path()))
+            .collect()
+    }
+
+    /// Short identifier derived from the blockhashes of all images in the pile.
+    ///
+    /// It does not depend on the order in which the images were loaded, so the same images always get the same id.
+    pub fn id(&self) -> String {
+        // FNV-1a, which unlike the std hashers is guaranteed to be stable
+        const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const PRIME: u64 = 0x0100_0000_01b3;
+
+        let hashes = self
+            .pictures
+            .iter()
+            .map(|image| image.hash().as_bytes())
+            .sorted_unstable();
+        let hash = hashes.flatten().fold(OFFSET_BASIS, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+        });
+        format!("{:08x}", (hash ^ (hash >> 32)) as u32)
+    }
+
+    /// Average GPS position of all images in the pile that have one
+    pub fn location(&self) -> Option<Location> {
+        Location::centroid(self.pictures.iter().filter_map(|p| p.location.as_ref()))
+    }
+
     pub fn new(image: Image) -> Self {
         Pile {
             date: image.timestamp.date(),
@@ -33,10 +86,87 @@ impl Pile {
     }
 
     pub fn merge(&mut self, other: Pile) {
-        self.pictures.extend(other.pictures);
+        self.extend(other.pictures);
+    }
+
+    /// Adds several images at once, which unlike [`Pile::push`] only updates the date once
+    pub fn extend(&mut self, images: impl IntoIterator<Item = Image>) {
+        self.pictures.extend(images);
         self.update_date();
     }
 
+    /// Splits the pile with average-linkage agglomerative clustering on the blockhash distance.
+    ///
+    /// Starting from single images, the two clusters with the smallest average distance are merged
+    /// as long as that distance is below `max_distance` and the merged cluster has at most `max_size` images.
+    /// The closest pairs are kept in a priority queue, so splitting takes O(n² log n) time for n images.
+    pub fn split(self, max_size: usize, max_distance: u32) -> Vec<Pile> {
+        let mut images: Vec<_> = self.pictures.into_iter().collect();
+        images.sort_unstable_by(|l, r| l.path().cmp(r.path()));
+
+        let n = images.len();
+        let mut distances =
+            CondensedMatrix::new(n, |i, j| images[i].hash_distance(&images[j]) as f32);
+        // cluster members by image index, `None` once merged into another cluster
+        let mut clusters: Vec<Option<Vec<usize>>> = (0..n).map(|i| Some(vec![i])).collect();
+        // bumped whenever a cluster grows, which outdates the candidates it is part of
+        let mut generations = vec![0; n];
+
+        let mut candidates: BinaryHeap<_> = (0..n)
+            .tuple_combinations()
+            .map(|(i, j)| Candidate::new(distances.get(i, j), (i, 0), (j, 0)))
+            .filter(|candidate| candidate.distance < max_distance as f32)
+            .collect();
+        while let Some(Candidate {
+            i, j, gen_i, gen_j, ..
+        }) = candidates.pop()
+        {
+            let (Some(l), Some(r)) = (&clusters[i], &clusters[j]) else {
+                continue;
+            };
+            // clusters only grow, so a pair that is too large stays too large
+            if generations[i] != gen_i || generations[j] != gen_j || l.len() + r.len() > max_size {
+                continue;
+            }
+
+            // Lance-Williams update for average linkage
+            let merged = clusters[j].take().expect("cluster j was not merged yet");
+            let (size_i, size_j) = (
+                clusters[i].as_ref().map_or(0, Vec::len) as f32,
+                merged.len() as f32,
+            );
+            clusters[i]
+                .as_mut()
+                .expect("cluster i was not merged yet")
+                .extend(merged);
+            generations[i] += 1;
+            for k in (0..n).filter(|&k| k != i && clusters[k].is_some()) {
+                let d = (size_i * distances.get(k, i) + size_j * distances.get(k, j))
+                    / (size_i + size_j);
+                distances.set(k, i, d);
+                if d < max_distance as f32 {
+                    candidates.push(Candidate::new(d, (k, generations[k]), (i, generations[i])));
+                }
+            }
+        }
+
+        let mut images: Vec<_> = images.into_iter().map(Some).collect();
+        clusters
+            .into_iter()
+            .flatten()
+            .map(|members| {
+                let mut members = members.into_iter().map(|m| {
+                    images[m]
+                        .take()
+                        .expect("every image belongs to exactly one cluster")
+                });
+                let mut pile = Pile::new(members.next().expect("clusters are never empty"));
+                pile.extend(members);
+                pile
+            })
+            .collect()
+    }
+
     fn update_date(&mut self) {
         self.date = self
             .pictures
@@ -47,3 +177,180 @@ impl Pile {
             .expect("computing pile date");
     }
 }
+
+/// Distances between all pairs of `n` items, storing each pair only once
+struct CondensedMatrix {
+    n: usize,
+    distances: Vec<f32>,
+}
+
+impl CondensedMatrix {
+    fn new(n: usize, distance: impl Fn(usize, usize) -> f32) -> Self {
+        let distances = (0..n)
+            .tuple_combinations()
+            .map(|(i, j)| distance(i, j))
+            .collect();
+        Self { n, distances }
+    }
+
+    fn index(&self, i: usize, j: usize) -> usize {
+        let (i, j) = if i < j { (i, j) } else { (j, i) };
+        i * self.n - i * (i + 1) / 2 + j - i - 1
+    }
+
+    fn get(&self, i: usize, j: usize) -> f32 {
+        self.distances[self.index(i, j)]
+    }
+
+    fn set(&mut self, i: usize, j: usize, distance: f32) {
+        let index = self.index(i, j);
+        self.distances[index] = distance;
+    }
+}
+
+/// Pair of clusters that may be merged, valid as long as both clusters have the recorded generation
+#[derive(Debug, PartialEq)]
+struct Candidate {
+    distance: f32,
+    i: usize,
+    j: usize,
+    gen_i: usize,
+    gen_j: usize,
+}
+
+impl Candidate {
+    fn new(distance: f32, (i, gen_i): (usize, usize), (j, gen_j): (usize, usize)) -> Self {
+        // the lower index comes first so ties are broken like in a scan over all pairs
+        let ((i, gen_i), (j, gen_j)) = if i < j {
+            ((i, gen_i), (j, gen_j))
+        } else {
+            ((j, gen_j), (i, gen_i))
+        };
+        Self {
+            distance,
+            i,
+            j,
+            gen_i,
+            gen_j,
+        }
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    /// Reversed so that the [`BinaryHeap`] pops the closest pair first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .distance
+            .total_cmp(&self.distance)
+            .then_with(|| (other.i, other.j).cmp(&(self.i, self.j)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use camino::Utf8Path;
+
+    use super::*;
+    use crate::testing::{temp_dir, test_image};
+
+    #[test]
+    fn id_is_independent_of_image_order() {
+        let dir = temp_dir("pile-id");
+        let images: Vec<_> = [2, 4, 8]
+            .into_iter()
+            .map(|squares| test_image(&dir, &format!("{squares}.png"), squares))
+            .collect();
+
+        let mut forward = Pile::new(images[0].clone());
+        forward.extend(images[1..].iter().cloned());
+        let mut backward = Pile::new(images[2].clone());
+        backward.push(images[1].clone());
+        backward.push(images[0].clone());
+        assert_eq!(forward.id(), backward.id());
+        assert_eq!(forward.id().len(), 8);
+
+        let smaller = Pile::new(images[0].clone());
+        assert_ne!(forward.id(), smaller.id());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn paths(pile: &Pile) -> Vec<&Utf8Path> {
+        pile.pictures.iter().map(Image::path).sorted().collect()
+    }
+
+    #[test]
+    fn split_keeps_small_piles() {
+        let dir = temp_dir("pile-split-small");
+        let mut pile = Pile::new(test_image(&dir, "a.png", 4));
+        pile.push(test_image(&dir, "b.png", 16));
+        let expected = paths(&pile)
+            .into_iter()
+            .map(Utf8Path::to_owned)
+            .collect_vec();
+
+        let piles = pile.split(2, u32::MAX);
+        assert_eq!(piles.len(), 1);
+        assert_eq!(paths(&piles[0]), expected);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn split_separated_clusters() {
+        let dir = temp_dir("pile-split-clusters");
+        let (a, b) = (
+            test_image(&dir, "a1.png", 4),
+            test_image(&dir, "b1.png", 16),
+        );
+        let between = a.hash_distance(&b);
+        assert!(between > 0);
+        let mut pile = Pile::new(a);
+        pile.push(b);
+        for name in ["a2.png", "a3.png"] {
+            pile.push(test_image(&dir, name, 4));
+        }
+        for name in ["b2.png", "b3.png"] {
+            pile.push(test_image(&dir, name, 16));
+        }
+
+        // merging the clusters is only ruled out by their distance, not by the size
+        let mut piles = pile.split(6, between);
+        piles.sort_unstable_by_key(|pile| paths(pile)[0].to_owned());
+        assert_eq!(piles.len(), 2);
+        assert_eq!(
+            paths(&piles[0]),
+            ["a1.png", "a2.png", "a3.png"].map(|name| dir.join(name))
+        );
+        assert_eq!(
+            paths(&piles[1]),
+            ["b1.png", "b2.png", "b3.png"].map(|name| dir.join(name))
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn split_respects_max_size() {
+        let dir = temp_dir("pile-split-size");
+        let mut pile = Pile::new(test_image(&dir, "0.png", 4));
+        for i in 1..7 {
+            pile.push(test_image(&dir, &format!("{i}.png"), 4 + i % 2));
+        }
+        let expected = paths(&pile)
+            .into_iter()
+            .map(Utf8Path::to_owned)
+            .collect_vec();
+
+        let piles = pile.split(3, u32::MAX);
+        assert!(piles.iter().all(|pile| pile.len() <= 3), "{piles:?}");
+        let split = piles.iter().flat_map(paths).sorted().collect_vec();
+        assert_eq!(split, expected);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,26 @@
+use camino::Utf8Path;
+
+use crate::image::ImageLoadError;
+
+/// Receives updates about the progress of loading, grouping and writing images.
+///
+/// The methods are called from several threads at once and do nothing by default.
+pub trait Progress: Sync {
+    /// The walk found `count` files that will be loaded
+    fn files_discovered(&self, _count: usize) {}
+    fn image_loaded(&self, _path: &Utf8Path) {}
+    /// Also called for files that were skipped because they are no images
+    fn image_failed(&self, _path: &Utf8Path, _error: &ImageLoadError) {}
+    /// Comparing `total` pairs of images starts
+    fn comparing_pairs(&self, _total: u64) {}
+    fn pairs_compared(&self, _count: u64) {}
+    /// Writing `total` piles into the destination starts
+    fn writing_piles(&self, _total: usize) {}
+    fn pile_written(&self, _path: &Utf8Path) {}
+}
+
+/// Ignores all progress updates
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoProgress;
+
+impl Progress for NoProgress {}
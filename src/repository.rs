@@ -1,234 +1,117 @@
-use std::time::Duration as StdDuration;
-
 use camino::{Utf8Path, Utf8PathBuf};
-use chrono::Duration;
-use color_eyre::eyre::{Context, ContextCompat, Result};
-use itertools::Itertools;
-use rayon::prelude::{IntoParallelRefIterator, ParallelBridge, ParallelIterator};
 
-use crate::image::Image;
+use crate::grouper::{Grouper, GroupingOptions};
+use crate::image::{Image, LoadOptions};
 use crate::pile::Pile;
-use crate::DATETIME_FORMATTER;
+use crate::progress::Progress;
+use crate::scanner::{LoadFailure, ScanOptions, Scanner};
+use crate::stats::Stats;
+use crate::writer::{OutputOptions, PileWriter, WriteError};
 
+/// Runs the [`Scanner`], [`Grouper`] and [`PileWriter`] stages one after another
 pub struct Repository {
+    /// Images that are not in a pile, i.e. all images if they were only scanned
+    images: Vec<Image>,
     pub piles: Vec<Pile>,
-    stats: Stats,
+    failures: Vec<LoadFailure>,
+    stats: Option<Stats>,
 }
 
 impl Repository {
-    pub fn new(src: Utf8PathBuf) -> Self {
-        use walkdir::WalkDir;
-
+    pub fn new(
+        sources: &[Utf8PathBuf],
+        scan_options: &ScanOptions,
+        load_options: &LoadOptions,
+        options: &GroupingOptions,
+        progress: &dyn Progress,
+    ) -> Self {
         let start = std::time::Instant::now();
 
-        let images: Vec<_> = WalkDir::new(src)
-            .into_iter()
-            .par_bridge()
-            .filter_map(|e| {
-                e.ok()
-                    .filter(|e| e.file_type().is_file())
-                    .and_then(|e| e.into_path().try_into().ok())
-            })
-            .filter_map(|path: Utf8PathBuf| {
-                let image = Image::load(&path)
-                    .map_err(|err| {
-                        tracing::error!("Failed to load image {path}: {err}");
-                        err
-                    })
-                    .ok()?;
-                Some(image)
-            })
-            .collect();
-
-        tracing::info!("Loaded {} images.", images.len());
-
-        let piles: Vec<_> = images
-            .iter()
-            .tuple_combinations::<(_, _)>()
-            .par_bridge()
-            .filter(|(l, r)| {
-                let time_delta = abs(l.timestamp - r.timestamp);
-                time_delta < chrono::Duration::minutes(30) && l.hash.dist(&r.hash) < 10
-            })
-            .chain(images.par_iter().map(|i| (i, i)))
-            .collect();
-
-        let piles = piles.into_iter().fold(Vec::with_capacity(images.len()), |mut piles: Vec<Pile>, (l, r)| {
-            let left_pile = piles.iter().position(|pile| pile.pictures.contains(l));
-            let right_pile = piles.iter().position(|pile| pile.pictures.contains(r));
-            match (left_pile, right_pile) {
-                (None, None) => {
-                    let mut pile = Pile::new(r.clone());
-                    pile.push(l.clone());
-                    piles.push(pile);
-                    let index = piles.len() - 1;
-                    tracing::debug!("Added picture {l} to pile {index}");
-                    if l != r {
-                        tracing::debug!("Added picture {r} to pile {index}");
-                    }
-                },
-                (Some(pile), None) => {
-                    piles[pile].push(r.clone());
-                    tracing::debug!("Added picture {r} to pile {pile} because it also contains picture {l}");
-                },
-                (None, Some(pile)) => {
-                    piles[pile].push(l.clone());
-                    tracing::debug!("Added picture {l} to pile {pile} because it also contains picture {r}");
-                },
-                (Some(i), Some(j)) => {
-                    if i != j {
-                        let disbanding_pile = piles.swap_remove(j);
-                        // swap_remove moved the pile we want to retain if i == max index -> now it is at index j
-                        let pile_index = if i == piles.len() { j } else { i };
-                        piles[pile_index].merge(disbanding_pile);
-                        tracing::debug!("Merged piles {i} and {j} to {i} because picture {l} belonged to pile {i} and picture {r} belonged to pile {j}");
-                    }
-                }
-            }
-            piles
-        });
-
-        tracing::trace!("{piles:#?}");
-        let elapsed = start.elapsed();
-
-        let stats = Stats::from_piles(&piles, elapsed);
-        stats.print_stats();
-
-        Self { piles, stats }
-    }
+        let mut scan = Scanner::new(sources.iter().cloned())
+            .scan_options(scan_options.clone())
+            .load_options(load_options.clone())
+            .progress(progress)
+            .scan();
+        // the images are moved into the piles
+        let grouping = Grouper::new(options.clone())
+            .progress(progress)
+            .group(std::mem::take(&mut scan.images));
+        let stats = Stats::new(&scan, &grouping, start.elapsed());
 
-    pub fn create_piles(&self, dest: &Utf8Path) -> Result<()> {
-        use std::collections::HashMap;
-        use std::fs;
-        let mut dates_counts = HashMap::with_capacity(self.piles.len());
-        for pile in &self.piles {
-            let n: usize = *dates_counts
-                .entry(pile.date())
-                .and_modify(|e| *e += 1)
-                .or_default();
-            let timestamp = format!("{}_{n:04}", pile.date());
-            let dir = dest.join(timestamp);
-            fs::create_dir(&dir)?;
-            for image in &pile.pictures {
-                let mut link = dir.clone();
-                link.push(image.path().file_name().wrap_err_with(|| {
-                    format!("Invalid image file name for path {}", image.path())
-                })?);
-                fs::hard_link(image.path(), link)?;
-            }
+        Self {
+            images: Vec::new(),
+            piles: grouping.piles,
+            failures: scan.failures,
+            stats: Some(stats),
         }
-
-        self.stats
-            .save_to_file(dest)
-            .wrap_err_with(|| format!("Failed to save stats file to {dest}"))?;
-        Ok(())
     }
-}
 
-fn abs(duration: Duration) -> Duration {
-    if duration < Duration::zero() {
-        -duration
-    } else {
-        duration
-    }
-}
-
-struct Stats {
-    longest_time_delta: i64,
-    total_pics: usize,
-    total_piles: usize,
-    max_pile_size: usize,
-    avg_pile_size: f32,
-    median_pile_size: usize,
-    run_time_ms: u128,
-}
-
-impl Stats {
-    fn from_piles(piles: &[Pile], run_time: StdDuration) -> Self {
-        let total_pics: usize = piles.iter().map(|p| p.pictures.len()).sum();
-        let total_piles = piles.len();
-        let sorted_piles: Vec<_> = piles
-            .iter()
-            .map(|p| p.pictures.len())
-            .sorted_unstable()
-            .collect();
+    /// Only loads the images below `sources` without grouping them into piles
+    pub fn scan(
+        sources: &[Utf8PathBuf],
+        scan_options: &ScanOptions,
+        load_options: &LoadOptions,
+        progress: &dyn Progress,
+    ) -> Self {
+        let scan = Scanner::new(sources.iter().cloned())
+            .scan_options(scan_options.clone())
+            .load_options(load_options.clone())
+            .progress(progress)
+            .scan();
         Self {
-            run_time_ms: run_time.as_millis(),
-            total_pics,
-            total_piles,
-            avg_pile_size: total_pics as f32 / total_piles as f32,
-            median_pile_size: sorted_piles[sorted_piles.len() / 2],
-            max_pile_size: piles
-                .iter()
-                .map(|p| p.pictures.len())
-                .max()
-                .unwrap_or_default(),
-            longest_time_delta: piles
-                .iter()
-                .map(|p| {
-                    use itertools::MinMaxResult;
-                    match p.pictures.iter().map(|image| image.timestamp).minmax() {
-                        MinMaxResult::NoElements | MinMaxResult::OneElement(_) => {
-                            chrono::Duration::zero()
-                        }
-                        MinMaxResult::MinMax(min, max) => max - min,
-                    }
-                })
-                .max()
-                .unwrap_or_else(Duration::zero)
-                .num_minutes(),
+            images: scan.images,
+            piles: Vec::new(),
+            failures: scan.failures,
+            stats: None,
         }
     }
 
-    fn print_stats(&self) {
-        let Self {
-            longest_time_delta,
-            total_pics,
-            total_piles,
-            max_pile_size,
-            avg_pile_size,
-            median_pile_size,
-            run_time_ms,
-        } = self;
-        tracing::info!("===== STATS =====");
-        tracing::info!("Run time: {run_time_ms}ms");
-        tracing::info!("Image count: {total_pics}");
-        tracing::info!("Pile count: {total_piles}");
-        tracing::info!(
-            "Pile size (Avg/Med/Max): {avg_pile_size}/{median_pile_size}/{max_pile_size}"
-        );
-        tracing::info!("Longest time delta: {longest_time_delta}min");
+    /// All images, whether they were grouped into piles or only scanned
+    pub fn images(&self) -> impl Iterator<Item = &Image> {
+        self.images
+            .iter()
+            .chain(self.piles.iter().flat_map(|pile| &pile.pictures))
     }
 
-    fn save_to_file(&self, dir: &Utf8Path) -> Result<()> {
-        use std::fs::File;
-        use std::io::Write;
-        let path = dir.join("info.txt");
-        let mut file = File::create(path)?;
-
-        let timestamp = chrono::offset::Local::now().format(DATETIME_FORMATTER);
+    /// Files that look like images but could not be loaded
+    pub fn failures(&self) -> &[LoadFailure] {
+        &self.failures
+    }
 
-        let Self {
-            longest_time_delta,
-            total_pics,
-            total_piles,
-            max_pile_size,
-            avg_pile_size,
-            median_pile_size,
-            run_time_ms,
-        } = self;
+    /// Statistics of the grouping, `None` if the images were only scanned
+    pub fn stats(&self) -> Option<&Stats> {
+        self.stats.as_ref()
+    }
 
-        writeln!(file, "===== STATS =====")?;
-        writeln!(file, "Sorting time: {timestamp}")?;
-        writeln!(file, "Run time: {run_time_ms}ms")?;
-        writeln!(file, "Image count: {total_pics}")?;
-        writeln!(file, "Pile count: {total_piles}")?;
-        writeln!(
-            file,
-            "Pile size (Avg/Med/Max): {avg_pile_size}/{median_pile_size}/{max_pile_size}"
-        )?;
-        writeln!(file, "Longest time delta: {longest_time_delta}min")?;
+    /// All images whose hash distance to `image` is below `threshold`, closest first.
+    /// Like [`GroupingOptions::max_hash_distance`] the threshold itself is not similar anymore.
+    ///
+    /// Images with the same distance are ordered by their timestamp.
+    pub fn find_similar(&self, image: &Image, threshold: u32) -> Vec<(&Image, u32)> {
+        let mut similar: Vec<_> = self
+            .images()
+            .map(|candidate| (candidate, candidate.hash_distance(image)))
+            .filter(|&(_, distance)| distance < threshold)
+            .collect();
+        similar.sort_by_key(|&(candidate, distance)| {
+            (distance, candidate.timestamp, candidate.path())
+        });
+        similar
+    }
 
-        Ok(())
+    pub fn create_piles(
+        &self,
+        dest: &Utf8Path,
+        options: &OutputOptions,
+        progress: &dyn Progress,
+    ) -> Result<(), WriteError> {
+        let mut writer = PileWriter::new(dest)
+            .options(options.clone())
+            .failures(&self.failures)
+            .progress(progress);
+        if let Some(ref stats) = self.stats {
+            writer = writer.stats(stats);
+        }
+        writer.write(&self.piles).map(|_| ())
     }
 }
@@ -0,0 +1,205 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Display;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::existing::pile_dirs;
+use crate::manifest::{Manifest, ManifestError, MANIFEST_FILE};
+use crate::stats::HumanBytes;
+
+/// Current state of a destination written by the [`PileWriter`](crate::PileWriter) while its piles are reviewed.
+///
+/// Unlike the [`Stats`](crate::Stats) of the sorting run it only looks at the files that are left in the
+/// pile folders and does not load any images. The original pile sizes are taken from the manifest if there is one.
+#[derive(Debug, Clone, Serialize)]
+pub struct Review {
+    /// Piles that still contain images
+    pub total_piles: usize,
+    pub total_pics: usize,
+    /// Number of piles per pile size
+    pub pile_size_histogram: BTreeMap<usize, usize>,
+    /// Number of piles with a single image
+    pub singletons: usize,
+    /// Size of all remaining images in bytes
+    pub total_bytes: u64,
+    /// Bytes freed if only the largest image of each pile was kept
+    pub reclaimable_bytes: u64,
+    /// Whether the destination has a manifest with the original piles
+    pub has_manifest: bool,
+    /// Piles with duplicates that were reduced to at most one image
+    pub reviewed_piles: usize,
+    /// Piles with duplicates of which some but not all were deleted
+    pub partially_reviewed_piles: usize,
+    /// Piles with duplicates that still contain all of their images
+    pub untouched_piles: usize,
+    /// Images of the manifest that were deleted from their pile
+    pub deleted_pics: usize,
+    /// Size of the deleted images that is freed when only the collected images are kept
+    pub deleted_bytes: u64,
+    pub piles: Vec<PileReview>,
+}
+
+/// Current state of a single pile folder
+#[derive(Debug, Clone, Serialize)]
+pub struct PileReview {
+    /// Pile folder relative to the destination
+    pub dir: Utf8PathBuf,
+    /// Number of images when the pile was written, `None` without a manifest
+    pub original_images: Option<usize>,
+    pub images: usize,
+    /// Size of the remaining images in bytes
+    pub bytes: u64,
+    /// Bytes freed if only the largest remaining image was kept
+    pub reclaimable_bytes: u64,
+}
+
+#[derive(Debug, Error)]
+pub enum ReviewError {
+    #[error("failed to read {0}")]
+    ReadDir(Utf8PathBuf, #[source] std::io::Error),
+    #[error(transparent)]
+    Manifest(#[from] ManifestError),
+}
+
+impl Review {
+    /// Inspects the pile folders below `dest`. Event folders are descended into, the [`UNREADABLE_PILE`](crate::UNREADABLE_PILE) is ignored
+    pub fn load(dest: &Utf8Path) -> Result<Self, ReviewError> {
+        let manifest_path = dest.join(MANIFEST_FILE);
+        let manifest = match manifest_path.exists() {
+            true => Some(Manifest::load(&manifest_path)?),
+            false => None,
+        };
+
+        let mut piles = BTreeMap::new();
+        let mut files = HashSet::new();
+        let dirs = pile_dirs(dest).map_err(|err| ReviewError::ReadDir(dest.to_owned(), err))?;
+        for dir in dirs {
+            let pile =
+                read_pile(dest, &dir, &mut files).map_err(|err| ReviewError::ReadDir(dir, err))?;
+            piles.insert(pile.dir.clone(), pile);
+        }
+
+        let (mut deleted_pics, mut deleted_bytes) = (0, 0);
+        if let Some(ref manifest) = manifest {
+            for entry in &manifest.entries {
+                let dir = entry.file.parent().unwrap_or(Utf8Path::new("")).to_owned();
+                let pile = piles.entry(dir.clone()).or_insert_with(|| new_pile(dir));
+                *pile.original_images.get_or_insert(0) += 1;
+                if !files.contains(&entry.file) {
+                    deleted_pics += 1;
+                    // the pile only held a link, the original is still in the source folder
+                    deleted_bytes += std::fs::metadata(&entry.source).map_or(0, |m| m.len());
+                }
+            }
+        }
+
+        let piles: Vec<_> = piles.into_values().collect();
+        let remaining = || piles.iter().filter(|p| p.images > 0);
+        let with_duplicates = || {
+            piles
+                .iter()
+                .filter_map(|p| Some((p.original_images?, p.images)))
+                .filter(|&(original, _)| original > 1)
+        };
+        let total_bytes = remaining().map(|p| p.bytes).sum();
+        Ok(Self {
+            total_piles: remaining().count(),
+            total_pics: remaining().map(|p| p.images).sum(),
+            pile_size_histogram: remaining().fold(BTreeMap::new(), |mut histogram, p| {
+                *histogram.entry(p.images).or_default() += 1;
+                histogram
+            }),
+            singletons: remaining().filter(|p| p.images == 1).count(),
+            total_bytes,
+            reclaimable_bytes: piles.iter().map(|p| p.reclaimable_bytes).sum(),
+            has_manifest: manifest.is_some(),
+            reviewed_piles: with_duplicates().filter(|&(_, now)| now <= 1).count(),
+            partially_reviewed_piles: with_duplicates()
+                .filter(|&(original, now)| now > 1 && now < original)
+                .count(),
+            untouched_piles: with_duplicates()
+                .filter(|&(original, now)| now >= original)
+                .count(),
+            deleted_pics,
+            deleted_bytes,
+            piles,
+        })
+    }
+}
+
+fn new_pile(dir: Utf8PathBuf) -> PileReview {
+    PileReview {
+        dir,
+        original_images: None,
+        images: 0,
+        bytes: 0,
+        reclaimable_bytes: 0,
+    }
+}
+
+/// Reads the remaining files of the pile in `dir`
+fn read_pile(
+    dest: &Utf8Path,
+    dir: &Utf8Path,
+    files: &mut HashSet<Utf8PathBuf>,
+) -> std::io::Result<PileReview> {
+    let mut sizes = Vec::new();
+    for entry in dir.read_dir_utf8()? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        if meta.is_file() {
+            let relative = entry.path().strip_prefix(dest).unwrap_or(entry.path());
+            files.insert(relative.to_owned());
+            sizes.push(meta.len());
+        }
+    }
+    let bytes = sizes.iter().sum();
+    Ok(PileReview {
+        images: sizes.len(),
+        bytes,
+        reclaimable_bytes: bytes - sizes.iter().max().copied().unwrap_or_default(),
+        ..new_pile(dir.strip_prefix(dest).unwrap_or(dir).to_owned())
+    })
+}
+
+impl Display for Review {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "Image count: {}", self.total_pics)?;
+        writeln!(f, "Pile count: {}", self.total_piles)?;
+        writeln!(f, "Single image piles: {}", self.singletons)?;
+        writeln!(f, "Pile size histogram:")?;
+        for (size, count) in &self.pile_size_histogram {
+            writeln!(f, "  {size}: {count}")?;
+        }
+        writeln!(f, "Total size: {}", HumanBytes(self.total_bytes))?;
+        writeln!(
+            f,
+            "Reclaimable by keeping one image per pile: {}",
+            HumanBytes(self.reclaimable_bytes)
+        )?;
+        if !self.has_manifest {
+            return writeln!(f, "Review progress unknown without {MANIFEST_FILE}");
+        }
+        let with_duplicates =
+            self.reviewed_piles + self.partially_reviewed_piles + self.untouched_piles;
+        writeln!(
+            f,
+            "Reviewed piles: {}/{with_duplicates}",
+            self.reviewed_piles
+        )?;
+        writeln!(
+            f,
+            "Partially reviewed piles: {}",
+            self.partially_reviewed_piles
+        )?;
+        writeln!(f, "Untouched piles: {}", self.untouched_piles)?;
+        writeln!(
+            f,
+            "Reclaimed on collect: {} in {} deleted images",
+            HumanBytes(self.deleted_bytes),
+            self.deleted_pics
+        )
+    }
+}
@@ -0,0 +1,355 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use globset::GlobSet;
+use rayon::prelude::{ParallelBridge, ParallelIterator};
+
+use crate::budget::MemoryBudget;
+use crate::image::{Image, ImageData, ImageLoadError, LoadOptions};
+use crate::progress::{NoProgress, Progress};
+use crate::sniff::FileKind;
+
+/// First stage that finds and loads all images below a set of source folders
+pub struct Scanner<'a> {
+    sources: Vec<Utf8PathBuf>,
+    scan_options: ScanOptions,
+    load_options: LoadOptions,
+    skip_files: HashSet<Utf8PathBuf>,
+    progress: &'a dyn Progress,
+}
+
+impl<'a> Scanner<'a> {
+    pub fn new<P: Into<Utf8PathBuf>>(sources: impl IntoIterator<Item = P>) -> Self {
+        Self {
+            sources: sources.into_iter().map(Into::into).collect(),
+            scan_options: ScanOptions::default(),
+            load_options: LoadOptions::default(),
+            skip_files: HashSet::new(),
+            progress: &NoProgress,
+        }
+    }
+
+    pub fn scan_options(mut self, scan_options: ScanOptions) -> Self {
+        self.scan_options = scan_options;
+        self
+    }
+
+    pub fn load_options(mut self, load_options: LoadOptions) -> Self {
+        self.load_options = load_options;
+        self
+    }
+
+    /// Does not load the files with these canonical paths, e.g. the [`ExistingPiles::sorted_sources`](crate::ExistingPiles::sorted_sources)
+    pub fn skip_files(mut self, skip_files: HashSet<Utf8PathBuf>) -> Self {
+        self.skip_files = skip_files;
+        self
+    }
+
+    pub fn progress(mut self, progress: &'a dyn Progress) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    pub fn sources(&self) -> &[Utf8PathBuf] {
+        &self.sources
+    }
+
+    /// Walks the sources and loads all files that pass the [`ScanOptions`].
+    ///
+    /// The files are read sequentially on a separate thread so that spinning disks do not have to seek
+    /// between them, while the images are decoded in parallel. Reading pauses whenever the decoders
+    /// fall behind or the images in flight exceed the memory budget of the [`LoadOptions`].
+    pub fn scan(&self) -> Scan {
+        let start = Instant::now();
+        let mut paths = self.scan_options.find_files(&self.sources);
+        if !self.skip_files.is_empty() {
+            paths.retain(|path| {
+                path.canonicalize_utf8()
+                    .map_or(true, |path| !self.skip_files.contains(&path))
+            });
+        }
+        let walk_time = start.elapsed();
+        tracing::debug!("Found {} files.", paths.len());
+        self.progress.files_discovered(paths.len());
+
+        let budget = MemoryBudget::new(self.load_options.memory_budget.unwrap_or(u64::MAX));
+        // bounds the files read ahead of the decoders even without a memory budget
+        let (sender, receiver) = std::sync::mpsc::sync_channel(rayon::current_num_threads());
+
+        let results: Vec<_> = std::thread::scope(|scope| {
+            let budget = &budget;
+            let paths = &paths;
+            scope.spawn(move || {
+                for path in paths {
+                    // reserve the file before it is read, the decoded pixels once its header is known
+                    let file_size = std::fs::metadata(path).map_or(0, |meta| meta.len());
+                    budget.acquire(file_size);
+                    let data = ImageData::load(path).map(|data| {
+                        let reserved = data.estimated_memory().max(file_size);
+                        budget.acquire_more(file_size, reserved - file_size);
+                        (data, reserved)
+                    });
+                    if data.is_err() {
+                        budget.release(file_size);
+                    }
+                    if sender.send((path, data)).is_err() {
+                        break;
+                    }
+                }
+            });
+
+            receiver
+                .into_iter()
+                .par_bridge()
+                .map(|(path, data)| {
+                    let image = data.and_then(|(data, reserved)| {
+                        let image = Image::from_data(data, &self.load_options);
+                        budget.release(reserved);
+                        image
+                    });
+                    match image {
+                        Ok(ref image) => self.progress.image_loaded(image.path()),
+                        Err(ref error) => self.progress.image_failed(path, error),
+                    }
+                    (path, image)
+                })
+                .collect()
+        });
+
+        let mut scan = Scan {
+            images: Vec::with_capacity(results.len()),
+            walk_time,
+            load_time: start.elapsed() - walk_time,
+            ..Default::default()
+        };
+        for (path, result) in results {
+            match result {
+                Ok(image) => scan.images.push(image),
+                Err(ImageLoadError::NotAnImage(kind)) => {
+                    tracing::debug!("Skipped {path}: file type {kind} is not an image");
+                    *scan.skipped_files.entry(kind).or_default() += 1;
+                }
+                Err(error) => scan.failures.push(LoadFailure {
+                    path: path.to_owned(),
+                    error,
+                }),
+            }
+        }
+
+        // the images were decoded in parallel and arrived in any order
+        scan.images.sort_unstable_by(|l, r| l.path().cmp(r.path()));
+        scan.failures.sort_unstable_by(|l, r| l.path.cmp(&r.path));
+        tracing::debug!("Loaded {} images.", scan.images.len());
+        scan
+    }
+}
+
+/// Result of a [`Scanner`]
+#[derive(Debug, Default)]
+pub struct Scan {
+    pub images: Vec<Image>,
+    /// Files that look like images but could not be loaded
+    pub failures: Vec<LoadFailure>,
+    /// Number of files per type that were not loaded because they are no images
+    pub skipped_files: BTreeMap<FileKind, usize>,
+    /// Time spent finding the files
+    pub walk_time: Duration,
+    /// Time spent reading and decoding the files
+    pub load_time: Duration,
+}
+
+/// Image file that could not be loaded
+#[derive(Debug)]
+pub struct LoadFailure {
+    pub path: Utf8PathBuf,
+    pub error: ImageLoadError,
+}
+
+impl LoadFailure {
+    /// Error message including all underlying causes
+    pub fn message(&self) -> String {
+        let mut message = self.error.to_string();
+        let mut source = std::error::Error::source(&self.error);
+        while let Some(cause) = source {
+            message.push_str(": ");
+            message.push_str(&cause.to_string());
+            source = cause.source();
+        }
+        message
+    }
+}
+
+/// Filters deciding which files are loaded while walking the source folders
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    /// Only load files whose path relative to their source folder matches one of these globs
+    pub include: Option<GlobSet>,
+    /// Skip files and folders whose path relative to their source folder matches one of these globs
+    pub exclude: Option<GlobSet>,
+    /// Only load files with one of these extensions, compared case-insensitively. Loads all files if empty
+    pub extensions: Vec<String>,
+    /// Skip files and folders whose name starts with a dot, e.g. thumbnail caches.
+    /// Enabled by default, just like in the CLI which only loads them with `--hidden`
+    pub skip_hidden: bool,
+    /// Minimum file size in bytes
+    pub min_size: Option<u64>,
+    /// Maximum file size in bytes
+    pub max_size: Option<u64>,
+    /// Maximum folder depth below the source folders. 1 only loads the files directly inside them
+    pub max_depth: Option<usize>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            include: None,
+            exclude: None,
+            extensions: Vec::new(),
+            skip_hidden: true,
+            min_size: None,
+            max_size: None,
+            max_depth: None,
+        }
+    }
+}
+
+impl ScanOptions {
+    /// All files below `sources` that pass the filters. Files found in several sources are only returned once
+    pub fn find_files(&self, sources: &[Utf8PathBuf]) -> Vec<Utf8PathBuf> {
+        use walkdir::WalkDir;
+
+        let mut paths: Vec<_> = sources
+            .iter()
+            .flat_map(|src| {
+                let mut walker = WalkDir::new(src);
+                if let Some(max_depth) = self.max_depth {
+                    walker = walker.max_depth(max_depth);
+                }
+                walker
+                    .into_iter()
+                    .filter_entry(move |e| self.enters(src, e))
+                    .filter_map(|e| e.ok())
+                    .filter(move |e| e.file_type().is_file() && self.accepts(src, e))
+                    .filter_map(|e| Utf8PathBuf::try_from(e.into_path()).ok())
+            })
+            .collect();
+        paths.sort_unstable();
+        paths.dedup();
+        paths
+    }
+
+    /// Whether the file at `path` below `source` passes the filters.
+    /// Unlike [`find_files`](Self::find_files), this checks a single file that was found by other means, e.g. a file watcher
+    pub fn accepts_path(&self, source: &Utf8Path, path: &Utf8Path) -> bool {
+        let Ok(relative) = path.strip_prefix(source) else {
+            return false;
+        };
+        let depth = relative.components().count();
+        // every folder on the way would have to be entered by a walk
+        let entered = relative
+            .ancestors()
+            .filter(|ancestor| !ancestor.as_str().is_empty())
+            .all(|ancestor| self.enters_relative(ancestor.as_std_path()));
+        self.max_depth.is_none_or(|max_depth| depth <= max_depth)
+            && entered
+            && self.accepts_relative(relative.as_std_path(), || {
+                std::fs::metadata(path).ok().map(|meta| meta.len())
+            })
+    }
+
+    /// Whether the walk should continue into `entry`, checks the filters shared by files and folders
+    fn enters(&self, src: &Utf8Path, entry: &walkdir::DirEntry) -> bool {
+        entry.depth() == 0
+            || entry
+                .path()
+                .strip_prefix(src)
+                .map_or(true, |relative| self.enters_relative(relative))
+    }
+
+    fn enters_relative(&self, relative: &Path) -> bool {
+        let hidden = relative
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        let excluded = self
+            .exclude
+            .as_ref()
+            .is_some_and(|exclude| exclude.is_match(relative));
+        !(excluded || self.skip_hidden && hidden)
+    }
+
+    fn accepts(&self, src: &Utf8Path, entry: &walkdir::DirEntry) -> bool {
+        entry.path().strip_prefix(src).is_ok_and(|relative| {
+            self.accepts_relative(relative, || entry.metadata().ok().map(|meta| meta.len()))
+        })
+    }
+
+    fn accepts_relative(&self, relative: &Path, len: impl FnOnce() -> Option<u64>) -> bool {
+        let included = self
+            .include
+            .as_ref()
+            .is_none_or(|include| include.is_match(relative));
+        let extension = self.extensions.is_empty()
+            || relative.extension().is_some_and(|extension| {
+                self.extensions
+                    .iter()
+                    .any(|allowed| extension.eq_ignore_ascii_case(allowed.trim_start_matches('.')))
+            });
+        let size = match (self.min_size, self.max_size) {
+            (None, None) => true,
+            (min, max) => len().is_some_and(|len| {
+                min.is_none_or(|min| len >= min) && max.is_none_or(|max| len <= max)
+            }),
+        };
+        included && extension && size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use globset::{Glob, GlobSetBuilder};
+
+    use super::*;
+    use crate::testing::temp_dir;
+
+    #[test]
+    fn accepts_path_like_find_files() {
+        let dir = temp_dir("scanner-accepts");
+        let files = [
+            "a.jpg",
+            "b.png",
+            "sub/c.jpg",
+            "sub/deep/d.jpg",
+            ".hidden/e.jpg",
+            "skipped/f.jpg",
+        ];
+        for file in files {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"not an image").unwrap();
+        }
+        let options = ScanOptions {
+            exclude: Some(
+                GlobSetBuilder::new()
+                    .add(Glob::new("skipped").unwrap())
+                    .build()
+                    .unwrap(),
+            ),
+            extensions: vec!["jpg".to_owned()],
+            max_depth: Some(2),
+            ..Default::default()
+        };
+
+        let found = options.find_files(std::slice::from_ref(&dir));
+        assert_eq!(found, [dir.join("a.jpg"), dir.join("sub/c.jpg")]);
+        let accepted: Vec<_> = files
+            .iter()
+            .map(|file| dir.join(file))
+            .filter(|path| options.accepts_path(&dir, path))
+            .collect();
+        assert_eq!(accepted, found);
+        assert!(!options.accepts_path(&dir.join("sub"), &dir.join("a.jpg")));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,134 @@
+use std::fmt::Display;
+
+use serde::Serialize;
+
+/// Number of bytes read from the start of a file to determine its type
+pub(crate) const SNIFF_SIZE: u64 = 4096;
+
+/// Coarse type of a file determined from its magic bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileKind {
+    /// Any format the image decoder understands, or an image container like HEIC
+    Image,
+    Video,
+    Audio,
+    /// Compressed archives like zip, tar or 7z
+    Archive,
+    Pdf,
+    /// None of the known signatures matched
+    Unknown,
+}
+
+impl FileKind {
+    /// Determines the file type from the first bytes of a file
+    pub fn from_header(header: &[u8]) -> Self {
+        let at =
+            |offset: usize, magic: &[u8]| header.get(offset..offset + magic.len()) == Some(magic);
+        // the image decoder takes every RIFF container for a WebP image
+        if at(0, b"RIFF") {
+            return match header.get(8..12) {
+                Some(b"WEBP") => FileKind::Image,
+                Some(b"AVI ") => FileKind::Video,
+                Some(b"WAVE") => FileKind::Audio,
+                _ => FileKind::Unknown,
+            };
+        }
+        if image::guess_format(header).is_ok() {
+            return FileKind::Image;
+        }
+
+        if at(4, b"ftyp") {
+            // ISO base media files are either HEIF images or MP4/QuickTime videos
+            return match header.get(8..12) {
+                Some(b"heic" | b"heix" | b"hevc" | b"mif1" | b"msf1" | b"avif" | b"avis") => {
+                    FileKind::Image
+                }
+                _ => FileKind::Video,
+            };
+        }
+
+        let video = at(0, &[0x1a, 0x45, 0xdf, 0xa3]) // Matroska, WebM
+            || at(0, &[0x00, 0x00, 0x01, 0xba]) // MPEG program stream
+            || at(0, &[0x30, 0x26, 0xb2, 0x75]) // ASF, WMV
+            || at(0, b"FLV")
+            || (at(0, &[0x47]) && at(188, &[0x47])); // MPEG transport stream
+        let audio = at(0, b"ID3")
+            || at(0, b"fLaC")
+            || at(0, b"OggS")
+            || (at(0, b"FORM") && at(8, b"AIFF"))
+            || header.first() == Some(&0xff) && header.get(1).is_some_and(|b| b & 0xe0 == 0xe0); // MPEG audio frame
+        let archive = at(0, b"PK\x03\x04")
+            || at(0, b"Rar!")
+            || at(0, &[0x37, 0x7a, 0xbc, 0xaf, 0x27, 0x1c]) // 7z
+            || at(0, &[0x1f, 0x8b]) // gzip
+            || at(0, b"BZh")
+            || at(0, &[0xfd, b'7', b'z', b'X', b'Z', 0x00])
+            || at(0, &[0x28, 0xb5, 0x2f, 0xfd]) // zstd
+            || at(257, b"ustar");
+
+        if video {
+            FileKind::Video
+        } else if audio {
+            FileKind::Audio
+        } else if archive {
+            FileKind::Archive
+        } else if at(0, b"%PDF") {
+            FileKind::Pdf
+        } else {
+            FileKind::Unknown
+        }
+    }
+}
+
+impl Display for FileKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            FileKind::Image => "image",
+            FileKind::Video => "video",
+            FileKind::Audio => "audio",
+            FileKind::Archive => "archive",
+            FileKind::Pdf => "pdf",
+            FileKind::Unknown => "unknown",
+        };
+        f.write_str(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `magic` at `offset`, padded with zeros
+    fn header(offset: usize, magic: &[u8]) -> Vec<u8> {
+        let mut header = vec![0; offset + magic.len() + 16];
+        header[offset..offset + magic.len()].copy_from_slice(magic);
+        header
+    }
+
+    #[test]
+    fn detect_file_kinds() {
+        let kind = |offset, magic: &[u8]| FileKind::from_header(&header(offset, magic));
+        assert_eq!(kind(0, b"\x89PNG\r\n\x1a\n"), FileKind::Image);
+        assert_eq!(kind(0, &[0xff, 0xd8, 0xff]), FileKind::Image);
+        assert_eq!(kind(4, b"ftypheic"), FileKind::Image);
+        assert_eq!(kind(4, b"ftypisom"), FileKind::Video);
+        assert_eq!(kind(0, b"RIFF\0\0\0\0WEBP"), FileKind::Image);
+        assert_eq!(kind(0, b"RIFF\0\0\0\0AVI "), FileKind::Video);
+        assert_eq!(kind(0, b"RIFF\0\0\0\0WAVE"), FileKind::Audio);
+        assert_eq!(kind(0, &[0x1a, 0x45, 0xdf, 0xa3]), FileKind::Video);
+        assert_eq!(kind(0, b"ID3"), FileKind::Audio);
+        assert_eq!(kind(0, &[0xff, 0xfb]), FileKind::Audio);
+        assert_eq!(kind(0, b"PK\x03\x04"), FileKind::Archive);
+        assert_eq!(kind(257, b"ustar"), FileKind::Archive);
+        assert_eq!(kind(0, b"%PDF-1.7"), FileKind::Pdf);
+        assert_eq!(kind(0, b"hello world"), FileKind::Unknown);
+    }
+
+    #[test]
+    fn short_headers_are_unknown() {
+        assert_eq!(FileKind::from_header(&[]), FileKind::Unknown);
+        assert_eq!(FileKind::from_header(b"RIFF"), FileKind::Unknown);
+        assert_eq!(FileKind::from_header(b"\0\0\0\0ftyp"), FileKind::Video);
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::time::Duration as StdDuration;
+
+use camino::Utf8PathBuf;
+use chrono::{Duration, NaiveDate};
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::grouper::{Grouping, SplitPile};
+use crate::image::TimestampSource;
+use crate::pile::Pile;
+use crate::scanner::Scan;
+use crate::sniff::FileKind;
+
+/// Summary of a sorting run
+#[derive(Debug, Clone, Serialize)]
+pub struct Stats {
+    /// Longest time between the first and last image of a pile in minutes
+    pub longest_time_delta: i64,
+    pub total_pics: usize,
+    /// Number of images with a GPS position
+    pub located_pics: usize,
+    pub total_piles: usize,
+    pub max_pile_size: usize,
+    pub avg_pile_size: f32,
+    pub median_pile_size: usize,
+    /// Number of piles per pile size
+    pub pile_size_histogram: BTreeMap<usize, usize>,
+    /// Number of piles with a single image
+    pub singletons: usize,
+    /// Share of images that are not the only image of their pile
+    pub duplicate_ratio: f32,
+    /// Size of all images in bytes
+    pub total_bytes: u64,
+    /// Bytes freed if only the largest image of each pile was kept
+    pub reclaimable_bytes: u64,
+    /// Number of images per format, e.g. `jpg`
+    pub formats: BTreeMap<String, usize>,
+    /// Number of images per source of their timestamp
+    pub timestamp_sources: BTreeMap<TimestampSource, usize>,
+    pub split_piles: Vec<SplitPile>,
+    /// Number of files per type that were not loaded because they are no images
+    pub skipped_files: BTreeMap<FileKind, usize>,
+    /// Number of files that look like images but could not be loaded
+    pub failed_files: usize,
+    pub run_time_ms: u128,
+    pub timings: Timings,
+    /// Size of every pile, ordered like the piles of the grouping
+    pub piles: Vec<PileSummary>,
+}
+
+/// Time spent in each phase of a sorting run in milliseconds
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Timings {
+    /// Finding the files below the sources
+    pub walk_ms: u128,
+    /// Reading and decoding the images
+    pub decode_ms: u128,
+    /// Comparing the images and grouping them into piles
+    pub compare_ms: u128,
+    /// Linking the piles into the destination, zero until they were written
+    pub write_ms: u128,
+}
+
+/// Size of a single pile
+#[derive(Debug, Clone, Serialize)]
+pub struct PileSummary {
+    pub date: NaiveDate,
+    /// Earliest image of the pile
+    pub first_image: Utf8PathBuf,
+    pub images: usize,
+    /// Size of all images of the pile in bytes
+    pub bytes: u64,
+}
+
+impl PileSummary {
+    fn new(pile: &Pile) -> Self {
+        Self {
+            date: pile.date(),
+            first_image: pile.first().path().to_owned(),
+            images: pile.len(),
+            bytes: pile.pictures.iter().map(|image| image.size()).sum(),
+        }
+    }
+}
+
+impl Stats {
+    pub fn new(scan: &Scan, grouping: &Grouping, run_time: StdDuration) -> Self {
+        // images attached to existing piles count as piles of their own
+        let piles: Vec<&Pile> = grouping
+            .piles
+            .iter()
+            .chain(grouping.attached.iter().map(|(_, pile)| pile))
+            .collect();
+        let images = || piles.iter().flat_map(|p| &p.pictures);
+        let total_pics: usize = piles.iter().map(|p| p.pictures.len()).sum();
+        let total_piles = piles.len();
+        let sorted_piles: Vec<_> = piles
+            .iter()
+            .map(|p| p.pictures.len())
+            .sorted_unstable()
+            .collect();
+        let pile_size_histogram = sorted_piles.iter().copied().counts().into_iter().collect();
+        let total_bytes = images().map(|image| image.size()).sum();
+        let kept_bytes: u64 = piles
+            .iter()
+            .filter_map(|p| p.pictures.iter().map(|image| image.size()).max())
+            .sum();
+        Self {
+            run_time_ms: run_time.as_millis(),
+            timings: Timings {
+                walk_ms: scan.walk_time.as_millis(),
+                decode_ms: scan.load_time.as_millis(),
+                compare_ms: grouping.compare_time.as_millis(),
+                write_ms: 0,
+            },
+            total_pics,
+            located_pics: images().filter(|image| image.location.is_some()).count(),
+            total_piles,
+            singletons: sorted_piles.iter().take_while(|&&size| size == 1).count(),
+            pile_size_histogram,
+            duplicate_ratio: match total_pics {
+                0 => 0.0,
+                n => (n - total_piles) as f32 / n as f32,
+            },
+            total_bytes,
+            reclaimable_bytes: total_bytes - kept_bytes,
+            formats: images()
+                .map(|image| match image.format() {
+                    Some(format) => format.extensions_str()[0].to_owned(),
+                    None => "unknown".to_owned(),
+                })
+                .counts()
+                .into_iter()
+                .collect(),
+            timestamp_sources: images()
+                .map(|image| image.timestamp_source)
+                .counts()
+                .into_iter()
+                .collect(),
+            piles: piles.iter().copied().map(PileSummary::new).collect(),
+            split_piles: grouping.split_piles.clone(),
+            skipped_files: scan.skipped_files.clone(),
+            failed_files: scan.failures.len(),
+            avg_pile_size: match total_piles {
+                0 => 0.0,
+                n => total_pics as f32 / n as f32,
+            },
+            median_pile_size: sorted_piles
+                .get(sorted_piles.len() / 2)
+                .copied()
+                .unwrap_or_default(),
+            max_pile_size: sorted_piles.last().copied().unwrap_or_default(),
+            longest_time_delta: piles
+                .iter()
+                .map(|p| {
+                    use itertools::MinMaxResult;
+                    match p.pictures.iter().map(|image| image.timestamp).minmax() {
+                        MinMaxResult::NoElements | MinMaxResult::OneElement(_) => {
+                            chrono::Duration::zero()
+                        }
+                        MinMaxResult::MinMax(min, max) => max - min,
+                    }
+                })
+                .max()
+                .unwrap_or_else(Duration::zero)
+                .num_minutes(),
+        }
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let Self {
+            longest_time_delta,
+            total_pics,
+            located_pics,
+            total_piles,
+            max_pile_size,
+            avg_pile_size,
+            median_pile_size,
+            pile_size_histogram,
+            singletons,
+            duplicate_ratio,
+            total_bytes,
+            reclaimable_bytes,
+            formats,
+            timestamp_sources,
+            split_piles,
+            skipped_files,
+            failed_files,
+            run_time_ms,
+            timings,
+            piles: _,
+        } = self;
+
+        writeln!(f, "Run time: {run_time_ms}ms")?;
+        writeln!(
+            f,
+            "  Walk/Decode/Compare/Write: {}ms/{}ms/{}ms/{}ms",
+            timings.walk_ms, timings.decode_ms, timings.compare_ms, timings.write_ms
+        )?;
+        writeln!(f, "Image count: {total_pics}")?;
+        writeln!(f, "Images with location: {located_pics}")?;
+        writeln!(f, "Image formats:")?;
+        for (format, count) in formats {
+            writeln!(f, "  {format}: {count}")?;
+        }
+        writeln!(f, "Timestamp sources:")?;
+        for (source, count) in timestamp_sources {
+            writeln!(f, "  {source}: {count}")?;
+        }
+        writeln!(f, "Pile count: {total_piles}")?;
+        writeln!(f, "Single image piles: {singletons}")?;
+        writeln!(
+            f,
+            "Pile size (Avg/Med/Max): {avg_pile_size}/{median_pile_size}/{max_pile_size}"
+        )?;
+        writeln!(f, "Pile size histogram:")?;
+        for (size, count) in pile_size_histogram {
+            writeln!(f, "  {size}: {count}")?;
+        }
+        writeln!(f, "Duplicate ratio: {:.1}%", duplicate_ratio * 100.0)?;
+        writeln!(f, "Total size: {}", HumanBytes(*total_bytes))?;
+        writeln!(
+            f,
+            "Reclaimable by keeping one image per pile: {}",
+            HumanBytes(*reclaimable_bytes)
+        )?;
+        writeln!(f, "Longest time delta: {longest_time_delta}min")?;
+        writeln!(f, "Split piles: {}", split_piles.len())?;
+        for split in split_piles {
+            writeln!(f, "  {split}")?;
+        }
+        writeln!(
+            f,
+            "Skipped files: {}",
+            skipped_files.values().sum::<usize>()
+        )?;
+        for (kind, count) in skipped_files {
+            writeln!(f, "  {kind}: {count}")?;
+        }
+        writeln!(f, "Failed files: {failed_files}")?;
+        Ok(())
+    }
+}
+
+/// Formats a byte count with a binary unit, e.g. `1.5 MiB`
+pub(crate) struct HumanBytes(pub u64);
+
+impl Display for HumanBytes {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+        if self.0 < 1024 {
+            return write!(f, "{} B", self.0);
+        }
+        let mut size = self.0 as f64 / 1024.0;
+        let mut unit = UNITS[0];
+        for next in &UNITS[1..] {
+            if size < 1024.0 {
+                break;
+            }
+            size /= 1024.0;
+            unit = next;
+        }
+        write!(f, "{size:.1} {unit}")
+    }
+}
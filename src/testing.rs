@@ -0,0 +1,33 @@
+//! Helpers shared by the unit tests
+
+use camino::{Utf8Path, Utf8PathBuf};
+use image::{Rgb, RgbImage};
+
+use crate::image::Image;
+
+/// Empty folder in the system temp folder that is unique to the test `name`
+pub(crate) fn temp_dir(name: &str) -> Utf8PathBuf {
+    let dir = Utf8PathBuf::try_from(std::env::temp_dir())
+        .expect("temp folder is valid UTF-8")
+        .join(format!("samepic-{}-{name}", std::process::id()));
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir).expect("removing old test folder");
+    }
+    std::fs::create_dir_all(&dir).expect("creating test folder");
+    dir
+}
+
+/// Writes and loads a small PNG with a checkerboard pattern. Different `squares` give different hashes,
+/// but finer patterns than 8 squares per edge blur into a flat image
+pub(crate) fn test_image(dir: &Utf8Path, name: &str, squares: u32) -> Image {
+    let path = dir.join(name);
+    RgbImage::from_fn(64, 64, |x, y| {
+        match (x * squares / 64 + y * squares / 64) % 2 {
+            0 => Rgb([0, 0, 0]),
+            _ => Rgb([255, 255, 255]),
+        }
+    })
+    .save(&path)
+    .expect("writing test image");
+    Image::load(&path).expect("loading test image")
+}
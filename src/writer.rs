@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufWriter, Write};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use chrono::Duration;
+use thiserror::Error;
+
+use crate::event::cluster_events;
+use crate::existing::ExistingPiles;
+use crate::geocode::Gazetteer;
+use crate::manifest::{write_manifest, MANIFEST_FILE};
+use crate::pile::Pile;
+use crate::progress::{NoProgress, Progress};
+use crate::scanner::LoadFailure;
+use crate::stats::Stats;
+use crate::DATETIME_FORMATTER;
+
+/// Last stage that hard-links the images of each pile into its own folder of the destination
+pub struct PileWriter<'a> {
+    dest: Utf8PathBuf,
+    options: OutputOptions<'a>,
+    stats: Option<&'a Stats>,
+    failures: &'a [LoadFailure],
+    existing: Option<(&'a ExistingPiles, &'a [(usize, Pile)])>,
+    progress: &'a dyn Progress,
+}
+
+/// Options for the folder structure written by the [`PileWriter`]
+#[derive(Debug, Clone, Default)]
+pub struct OutputOptions<'a> {
+    /// Group piles into event folders, starting a new event whenever there is
+    /// a time gap between two piles larger than the given duration
+    pub event_gap: Option<Duration>,
+    /// Also start a new event when two consecutive piles are further apart than the given distance in kilometers
+    pub event_distance_km: Option<f64>,
+    /// Name pile and event folders after the nearest place if their images have GPS positions
+    pub gazetteer: Option<&'a Gazetteer>,
+    /// Also link the files that could not be loaded into the folder [`UNREADABLE_PILE`]
+    pub link_unreadable: bool,
+    /// Name pile folders after the [`Pile::id`] instead of numbering the piles of each day
+    pub pile_ids: bool,
+}
+
+/// Name of the folder that collects the files that could not be loaded
+pub const UNREADABLE_PILE: &str = "_unreadable";
+
+/// Name of the file in the destination that lists the files that could not be loaded
+pub(crate) const FAILURES_FILE: &str = "failed.txt";
+
+#[derive(Debug, Error)]
+pub enum WriteError {
+    #[error("failed to create directory {0}")]
+    CreateDir(Utf8PathBuf, #[source] std::io::Error),
+    #[error("failed to link {0} to {1}")]
+    Link(Utf8PathBuf, Utf8PathBuf, #[source] std::io::Error),
+    #[error("invalid image file name for path {0}")]
+    InvalidFileName(Utf8PathBuf),
+    #[error("failed to write {0}")]
+    WriteFile(Utf8PathBuf, #[source] std::io::Error),
+}
+
+impl<'a> PileWriter<'a> {
+    /// Writes into `dest`, which has to exist already
+    pub fn new(dest: impl Into<Utf8PathBuf>) -> Self {
+        Self {
+            dest: dest.into(),
+            options: OutputOptions::default(),
+            stats: None,
+            failures: &[],
+            existing: None,
+            progress: &NoProgress,
+        }
+    }
+
+    pub fn options(mut self, options: OutputOptions<'a>) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Also write the stats into `info.txt` and `stats.json`. When adding to an [`existing`](Self::existing)
+    /// destination they are named after the time of the run instead, e.g. `stats-2022-08-14T10-00-00.json`
+    pub fn stats(mut self, stats: &'a Stats) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Report the files that could not be loaded in `failed.txt`, which is appended to for an existing destination
+    pub fn failures(mut self, failures: &'a [LoadFailure]) -> Self {
+        self.failures = failures;
+        self
+    }
+
+    /// Adds to the destination of an earlier run instead of an empty one. New piles get folder names that are
+    /// not taken yet, the `attached` piles are linked into the folders of the `existing` piles they belong to
+    /// and the manifest is extended
+    pub fn existing(mut self, existing: &'a ExistingPiles, attached: &'a [(usize, Pile)]) -> Self {
+        self.existing = Some((existing, attached));
+        self
+    }
+
+    pub fn progress(mut self, progress: &'a dyn Progress) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    pub fn dest(&self) -> &Utf8Path {
+        &self.dest
+    }
+
+    /// Writes the piles and returns the folder of each pile in the same order
+    pub fn write(&self, piles: &[Pile]) -> Result<Vec<Utf8PathBuf>, WriteError> {
+        let start = std::time::Instant::now();
+        let dest = self.dest.as_path();
+        let options = &self.options;
+
+        // folder of each event with the indices of its piles
+        let events: Vec<(Utf8PathBuf, Vec<usize>)> = match options.event_gap {
+            Some(max_gap) => {
+                let mut names = HashMap::new();
+                cluster_events(piles, max_gap, options.event_distance_km)
+                    .into_iter()
+                    .map(|event| {
+                        let place = event
+                            .location()
+                            .and_then(|l| options.gazetteer?.place_name(&l));
+                        let name = match place {
+                            Some(place) => format!("{}_{place}", event.name()),
+                            None => event.name(),
+                        };
+                        let n: usize = *names
+                            .entry(name.clone())
+                            .and_modify(|e| *e += 1)
+                            .or_insert(1);
+                        let dir = match n {
+                            1 => dest.join(name),
+                            n => dest.join(format!("{name}_{n}")),
+                        };
+                        (dir, event.piles)
+                    })
+                    .collect()
+            }
+            None => vec![(dest.to_owned(), (0..piles.len()).collect())],
+        };
+
+        let (existing, attached) = match self.existing {
+            Some((existing, attached)) => (Some(existing), attached),
+            None => (None, &[][..]),
+        };
+        // folders of an earlier run must not be reused for new piles
+        let taken = |dir: &Utf8Path| existing.is_some() && dir.exists();
+
+        self.progress.writing_piles(piles.len() + attached.len());
+        let mut dirs = vec![Utf8PathBuf::new(); piles.len()];
+        let mut manifest = Vec::with_capacity(piles.iter().map(Pile::len).sum());
+        let mut names_counts = HashMap::with_capacity(piles.len());
+        for (event_dir, indices) in events {
+            if event_dir != dest && !taken(&event_dir) {
+                create_dir(&event_dir)?;
+            }
+            for index in indices {
+                let pile = &piles[index];
+                let place = pile
+                    .location()
+                    .and_then(|l| options.gazetteer?.place_name(&l));
+                // a bare date needs a counter, a name with a place or id only if it is taken
+                let unique = place.is_some() || options.pile_ids;
+                let mut name = match place {
+                    Some(place) => format!("{}_{place}", pile.date()),
+                    None => pile.date().to_string(),
+                };
+                if options.pile_ids {
+                    name = format!("{name}_{}", pile.id());
+                }
+                let dir = loop {
+                    let n: usize = *names_counts
+                        .entry(name.clone())
+                        .and_modify(|e| *e += 1)
+                        .or_default();
+                    let dir = match (unique, n) {
+                        (true, 0) => event_dir.join(&name),
+                        (_, n) => event_dir.join(format!("{name}_{n:04}")),
+                    };
+                    if !taken(&dir) {
+                        break dir;
+                    }
+                };
+                create_dir(&dir)?;
+                for image in pile.sorted() {
+                    let link = free_link(&dir, image.path())?;
+                    fs::hard_link(image.path(), &link).map_err(|err| {
+                        WriteError::Link(image.path().to_owned(), link.clone(), err)
+                    })?;
+                    manifest.push((link, image));
+                }
+                self.progress.pile_written(&dir);
+                dirs[index] = dir;
+            }
+        }
+        if let Some(existing) = existing {
+            for (index, pile) in attached {
+                let dir = &existing.dirs[*index];
+                for image in pile.sorted() {
+                    let link = free_link(dir, image.path())?;
+                    fs::hard_link(image.path(), &link).map_err(|err| {
+                        WriteError::Link(image.path().to_owned(), link.clone(), err)
+                    })?;
+                    manifest.push((link, image));
+                }
+                self.progress.pile_written(dir);
+            }
+        }
+
+        if !self.failures.is_empty() {
+            let path = dest.join(FAILURES_FILE);
+            write_failures(&path, self.failures, existing.is_some())
+                .map_err(|err| WriteError::WriteFile(path, err))?;
+            if options.link_unreadable {
+                self.link_unreadable()?;
+            }
+        }
+        let path = dest.join(MANIFEST_FILE);
+        write_manifest(&path, dest, &manifest, existing.is_some())
+            .map_err(|err| WriteError::WriteFile(path, err))?;
+        if let Some(stats) = self.stats {
+            let mut stats = stats.clone();
+            stats.timings.write_ms = start.elapsed().as_millis();
+            let timestamp = chrono::offset::Local::now().format(DATETIME_FORMATTER);
+            // the stats only cover the images of this run, keep those of earlier runs
+            let (info, json) = match existing {
+                Some(_) => (
+                    format!("info-{timestamp}.txt"),
+                    format!("stats-{timestamp}.json"),
+                ),
+                None => ("info.txt".to_owned(), "stats.json".to_owned()),
+            };
+            let path = dest.join(info);
+            write_info(&path, &stats, &timestamp.to_string())
+                .map_err(|err| WriteError::WriteFile(path, err))?;
+            let path = dest.join(json);
+            write_json(&path, &stats).map_err(|err| WriteError::WriteFile(path, err))?;
+        }
+        Ok(dirs)
+    }
+
+    fn link_unreadable(&self) -> Result<(), WriteError> {
+        let dir = self.dest.join(UNREADABLE_PILE);
+        // an earlier run may have created it already
+        if !dir.is_dir() {
+            create_dir(&dir)?;
+        }
+        for failure in self.failures {
+            let path = &failure.path;
+            let link = free_link(&dir, path)?;
+            fs::hard_link(path, &link)
+                .map_err(|err| WriteError::Link(path.clone(), link.clone(), err))?;
+        }
+        Ok(())
+    }
+}
+
+/// Replaces the characters of `name` that are not allowed or awkward in file names on any platform with `_`,
+/// i.e. whitespace, control characters, path separators and the characters reserved by Windows
+pub fn sanitize_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_whitespace() || c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+fn create_dir(dir: &Utf8Path) -> Result<(), WriteError> {
+    fs::create_dir(dir).map_err(|err| WriteError::CreateDir(dir.to_owned(), err))
+}
+
+/// Path in `dir` for a link to `path` that does not exist yet, adding a counter to the name if necessary
+fn free_link(dir: &Utf8Path, path: &Utf8Path) -> Result<Utf8PathBuf, WriteError> {
+    let (stem, extension) = match (path.file_stem(), path.extension()) {
+        (Some(stem), extension) => (stem, extension),
+        _ => return Err(WriteError::InvalidFileName(path.to_owned())),
+    };
+    // files from different source folders may share a name
+    let link = (1..)
+        .map(|n| {
+            let name = match (n, extension) {
+                (1, Some(extension)) => format!("{stem}.{extension}"),
+                (1, None) => stem.to_owned(),
+                (n, Some(extension)) => format!("{stem}-{n}.{extension}"),
+                (n, None) => format!("{stem}-{n}"),
+            };
+            dir.join(name)
+        })
+        .find(|link| !link.exists())
+        .expect("infinite iterator");
+    Ok(link)
+}
+
+fn write_info(path: &Utf8Path, stats: &Stats, timestamp: &str) -> std::io::Result<()> {
+    let mut file = fs::File::create(path)?;
+    writeln!(file, "===== STATS =====")?;
+    writeln!(file, "Sorting time: {timestamp}")?;
+    write!(file, "{stats}")
+}
+
+fn write_json(path: &Utf8Path, stats: &Stats) -> std::io::Result<()> {
+    let mut file = BufWriter::new(fs::File::create(path)?);
+    serde_json::to_writer_pretty(&mut file, stats)?;
+    writeln!(file)?;
+    file.flush()
+}
+
+/// Writes a tab separated list of all files that could not be loaded with the reason.
+/// With `append` the files are added to the end of the list if there is one already
+fn write_failures(path: &Utf8Path, failures: &[LoadFailure], append: bool) -> std::io::Result<()> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)?;
+    let new = file.metadata()?.len() == 0;
+    let mut file = BufWriter::new(file);
+    if new {
+        writeln!(file, "file\terror\tmessage")?;
+    }
+    for failure in failures {
+        let message = failure.message().replace(['\t', '\n'], " ");
+        writeln!(
+            file,
+            "{}\t{}\t{message}",
+            failure.path,
+            failure.error.kind()
+        )?;
+    }
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grouper::{Grouper, GroupingOptions};
+    use crate::manifest::Manifest;
+    use crate::testing::{temp_dir, test_image};
+
+    #[test]
+    fn sanitize_file_names() {
+        assert_eq!(sanitize_file_name("São Paulo"), "São_Paulo");
+        assert_eq!(
+            sanitize_file_name("a/b\\c:d*e?f\"g<h>i|j\tk\u{7}"),
+            "a_b_c_d_e_f_g_h_i_j_k_"
+        );
+    }
+
+    #[test]
+    fn link_images_with_the_same_name() {
+        let root = temp_dir("same-name");
+        let (a, b, dest) = (root.join("a"), root.join("b"), root.join("dest"));
+        for dir in [&a, &b, &dest] {
+            fs::create_dir(dir).unwrap();
+        }
+        // the same photo in two backups ends up in one pile
+        let images = vec![test_image(&a, "img.png", 4), test_image(&b, "img.png", 4)];
+        let grouping = Grouper::new(GroupingOptions::default()).group(images);
+        assert_eq!(grouping.piles.len(), 1);
+
+        let dirs = PileWriter::new(&dest).write(&grouping.piles).unwrap();
+        let mut names: Vec<_> = dirs[0]
+            .read_dir_utf8()
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_owned())
+            .collect();
+        names.sort_unstable();
+        assert_eq!(names, ["img-2.png", "img.png"]);
+        let manifest = Manifest::load(&dest.join(MANIFEST_FILE)).unwrap();
+        assert_eq!(manifest.entries.len(), 2);
+        fs::remove_dir_all(root).unwrap();
+    }
+}